@@ -0,0 +1 @@
+DROP TABLE published_posts;
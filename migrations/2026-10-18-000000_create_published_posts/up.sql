@@ -0,0 +1,9 @@
+CREATE TABLE published_posts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    show_id INTEGER NOT NULL,
+    season_number INTEGER NOT NULL,
+    channel TEXT NOT NULL,
+    post_id TEXT NOT NULL,
+    published_at TIMESTAMP NOT NULL,
+    UNIQUE (show_id, season_number, channel)
+);
@@ -0,0 +1,11 @@
+CREATE TABLE queued_posts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    show_id INTEGER NOT NULL,
+    season_number INTEGER NOT NULL,
+    channel TEXT NOT NULL,
+    season_data TEXT NOT NULL,
+    publish_at TIMESTAMP NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    failed_at TIMESTAMP,
+    UNIQUE (show_id, season_number, channel)
+);
@@ -1,7 +1,54 @@
 pub mod tv_maze;
 
-#[derive(Debug, Clone)]
+use crate::error::BotError;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use reqwest::header::HeaderMap;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Where new seasons come from, e.g. the tvmaze schedule
+#[async_trait]
+pub trait Source: Send + Sync {
+    fn name(&self) -> &str;
+    // New seasons premiering on the given day
+    async fn seasons(&self, date: NaiveDate) -> Result<Schedule, BotError>;
+    // Headers for downloading the season images
+    fn image_headers(&self) -> HeaderMap {
+        HeaderMap::new()
+    }
+    // false for a source with the same seasons for every day, it is only
+    // asked for the last day of a run so that nothing is counted twice
+    fn is_dated(&self) -> bool {
+        true
+    }
+}
+
+// New seasons of a day and how many schedule entries were skipped, by reason
+#[derive(Debug, Default)]
+pub struct Schedule {
+    pub seasons: Vec<SeasonData>,
+    pub skipped: BTreeMap<String, usize>,
+}
+
+impl Schedule {
+    pub fn skip(&mut self, reason: &str) {
+        *self.skipped.entry(reason.to_string()).or_default() += 1;
+    }
+}
+
+impl From<Vec<SeasonData>> for Schedule {
+    fn from(seasons: Vec<SeasonData>) -> Self {
+        Schedule {
+            seasons,
+            skipped: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SeasonData {
+    pub show_id: i32,
     pub title: String,
     pub url: String,
     pub language: Option<String>,
@@ -10,6 +57,9 @@ pub struct SeasonData {
     pub image_url: Option<String>,
     pub season_number: i32,
     pub host: Option<String>,
+    pub airstamp: Option<DateTime<Utc>>,
+    // local airtime from tvmaze, None when the release has no set time
+    pub airtime: Option<String>,
 }
 
-pub use tv_maze::TvMaze;
+pub use tv_maze::{TvMaze, TvMazeFile};
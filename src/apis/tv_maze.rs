@@ -1,24 +1,25 @@
-use super::SeasonData;
-use crate::requests::RequestData;
-use chrono::{DateTime, Utc};
-use reqwest::header;
-use reqwest::header::HeaderMap;
+use super::{Schedule, SeasonData, Source};
+use crate::error::BotError;
+use crate::requests::{self, RequestData};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
 use scraper::{Html, Selector};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::error::Error;
+use std::fs;
+use std::io::{self, Read};
 
 const TV_MAZE_URL: &str = "https://api.tvmaze.com/schedule/web";
 const TARGET_SHOW_NUMBER: i32 = 1;
 
 #[derive(Debug, Copy, Clone)]
 pub struct TvMaze<'a> {
-    target_date: DateTime<Utc>,
+    target_date: NaiveDate,
     target_genres: &'a Vec<String>,
 }
 
 impl<'a> TvMaze<'a> {
-    pub fn new(target_date: DateTime<Utc>, target_genres: &Vec<String>) -> TvMaze {
+    pub fn new(target_date: NaiveDate, target_genres: &Vec<String>) -> TvMaze<'_> {
         TvMaze {
             target_date,
             target_genres,
@@ -27,29 +28,91 @@ impl<'a> TvMaze<'a> {
 }
 
 impl<'a> TvMaze<'a> {
-    pub fn get_data(&self, json_source: &str) -> Result<Vec<SeasonData>, Box<dyn Error>> {
-        let mut new_seasons = vec![];
-        let json_seasons: Vec<NewRawSeason> = serde_json::from_str(json_source)?;
+    pub fn get_data(&self, json_source: &str) -> Result<Schedule, BotError> {
+        let mut schedule = Schedule::default();
+        let json_seasons: Vec<NewRawSeason> = serde_json::from_str(json_source)
+            .map_err(|err| BotError::Parse(format!("tvmaze schedule: {}", err)))?;
         for season in json_seasons.iter() {
             if !season.is_target_show_number(TARGET_SHOW_NUMBER) {
+                schedule.skip("not a season premiere");
                 continue;
             }
             if !season._embedded.show.is_target_genres(self.target_genres) {
+                schedule.skip("not a target genre");
                 continue;
             }
+            let Some(season_number) = season.season else {
+                schedule.skip("no season number");
+                continue;
+            };
             let new_season = SeasonData {
+                show_id: season._embedded.show.id,
                 title: season._embedded.show.name.to_string(),
                 url: season._embedded.show.url.to_string(),
                 language: season._embedded.show.language.clone(),
                 description: season._embedded.show.description(),
                 genres: season._embedded.show.genres.clone(),
                 image_url: season._embedded.show.image_url(),
-                season_number: season.season.unwrap(),
+                season_number,
                 host: season._embedded.show.host(),
+                airstamp: season.airstamp(),
+                airtime: season.airtime(),
             };
-            new_seasons.push(new_season);
+            schedule.seasons.push(new_season);
         }
-        Ok(new_seasons)
+        Ok(schedule)
+    }
+}
+
+// The web schedule of the given day from the api
+#[async_trait]
+impl<'a> Source for TvMaze<'a> {
+    fn name(&self) -> &str {
+        "tvmaze"
+    }
+
+    async fn seasons(&self, date: NaiveDate) -> Result<Schedule, BotError> {
+        let tv_maze = TvMaze::new(date, self.target_genres);
+        let response = requests::get(&tv_maze).await?;
+        tv_maze.get_data(&response)
+    }
+}
+
+// A saved /schedule/web response, read for the last day of a run only
+pub struct TvMazeFile<'a> {
+    tv_maze: TvMaze<'a>,
+    content: String,
+}
+
+impl<'a> TvMazeFile<'a> {
+    // Read the file ("-" for stdin) once, so that it can be used by every run
+    pub fn open(input: &str, target_genres: &'a Vec<String>) -> Result<Self, BotError> {
+        let content = if input == "-" {
+            let mut content = String::new();
+            io::stdin()
+                .read_to_string(&mut content)
+                .map_err(|err| BotError::io("stdin", err))?;
+            content
+        } else {
+            fs::read_to_string(input).map_err(|err| BotError::io(input, err))?
+        };
+        let tv_maze = TvMaze::new(chrono::Utc::now().date_naive(), target_genres);
+        Ok(Self { tv_maze, content })
+    }
+}
+
+#[async_trait]
+impl<'a> Source for TvMazeFile<'a> {
+    fn name(&self) -> &str {
+        "tvmaze"
+    }
+
+    async fn seasons(&self, _date: NaiveDate) -> Result<Schedule, BotError> {
+        self.tv_maze.get_data(&self.content)
+    }
+
+    fn is_dated(&self) -> bool {
+        false
     }
 }
 
@@ -63,22 +126,14 @@ impl<'a> RequestData for TvMaze<'a> {
             self.target_date.format("%Y-%m-%d").to_string(),
         )]
     }
-
-    fn headers(&self) -> HeaderMap {
-        let mut headers = HeaderMap::new();
-        let user_agent = "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/119.0";
-        headers.insert(
-            header::USER_AGENT,
-            header::HeaderValue::from_str(user_agent).unwrap(),
-        );
-        headers
-    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NewRawSeason {
     pub season: Option<i32>,
     pub number: Option<i32>,
+    pub airtime: Option<String>,
+    pub airstamp: Option<String>,
     pub _embedded: NewRawEmbedded,
 }
 
@@ -89,6 +144,22 @@ impl NewRawSeason {
             None => false,
         }
     }
+
+    pub fn airstamp(&self) -> Option<DateTime<Utc>> {
+        let airstamp = self.airstamp.as_ref()?;
+        match DateTime::parse_from_rfc3339(airstamp) {
+            Ok(dt) => Some(dt.with_timezone(&Utc)),
+            Err(_) => None,
+        }
+    }
+
+    // Web releases without a set time have an empty airtime
+    pub fn airtime(&self) -> Option<String> {
+        match &self.airtime {
+            Some(airtime) if !airtime.is_empty() => Some(airtime.clone()),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -98,6 +169,7 @@ pub struct NewRawEmbedded {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NewRawShow {
+    pub id: i32,
     pub url: String,
     pub name: String,
     pub language: Option<String>,
@@ -122,10 +194,7 @@ impl NewRawShow {
 impl NewRawShow {
     pub fn image_url(&self) -> Option<String> {
         match &self.image {
-            Some(i) => match i.get("original") {
-                Some(u) => Some(u.to_string()),
-                None => None,
-            },
+            Some(i) => i.get("original").map(|u| u.to_string()),
             None => None,
         }
     }
@@ -138,12 +207,16 @@ impl NewRawShow {
             None => return None,
         };
         let fragment = Html::parse_fragment(summary);
-        let selector = Selector::parse("p").unwrap();
-        let p = fragment.select(&selector).next().unwrap();
-        let texts = p.text().collect::<Vec<_>>();
-        let texts: Vec<String> = texts.iter().map(|t| t.to_string()).collect();
-        let text = texts.join("");
-        Some(text)
+        // the first paragraph, or the whole text of a summary without <p>
+        let text: String = match Selector::parse("p")
+            .ok()
+            .and_then(|selector| fragment.select(&selector).next())
+        {
+            Some(p) => p.text().collect(),
+            None => fragment.root_element().text().collect(),
+        };
+        let text = text.trim();
+        (!text.is_empty()).then(|| text.to_string())
     }
 }
 
@@ -165,3 +238,150 @@ impl NewRawShow {
 pub struct NewRawWebChannel {
     pub name: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const SCHEDULE: &str = r#"[
+        {
+            "id": 1,
+            "season": 2,
+            "number": 1,
+            "airtime": "21:00",
+            "airstamp": "2023-11-02T01:00:00+00:00",
+            "_embedded": {
+                "show": {
+                    "id": 10,
+                    "url": "https://www.tvmaze.com/shows/10/space-show",
+                    "name": "Space Show",
+                    "language": "English",
+                    "genres": ["Science-Fiction", "Drama"],
+                    "image": {"medium": "https://img/medium/1.jpg", "original": "https://img/original/1.jpg"},
+                    "summary": "<p>Ships <b>fly</b> to space.</p>",
+                    "webChannel": {"id": 1, "name": "Netflix"}
+                }
+            }
+        },
+        {
+            "id": 2,
+            "season": 1,
+            "number": 2,
+            "airtime": "",
+            "airstamp": "2023-11-02T12:00:00+00:00",
+            "_embedded": {
+                "show": {
+                    "id": 11,
+                    "url": "https://www.tvmaze.com/shows/11/dragon-show",
+                    "name": "Dragon Show",
+                    "language": null,
+                    "genres": ["Fantasy"],
+                    "image": null,
+                    "summary": null,
+                    "webChannel": null
+                }
+            }
+        },
+        {
+            "id": 3,
+            "season": 1,
+            "number": 1,
+            "_embedded": {
+                "show": {
+                    "id": 12,
+                    "url": "https://www.tvmaze.com/shows/12/cooking-show",
+                    "name": "Cooking Show",
+                    "language": "English",
+                    "genres": ["Food"],
+                    "image": null,
+                    "summary": null,
+                    "webChannel": null
+                }
+            }
+        }
+    ]"#;
+
+    #[test]
+    fn test_get_data() {
+        let target_genres = vec![String::from("Fantasy"), String::from("Science-Fiction")];
+        let tv_maze = TvMaze::new(Utc::now().date_naive(), &target_genres);
+        let schedule = tv_maze.get_data(SCHEDULE).unwrap();
+        assert_eq!(schedule.seasons.len(), 1);
+        assert_eq!(schedule.skipped["not a season premiere"], 1);
+        assert_eq!(schedule.skipped["not a target genre"], 1);
+        let season = &schedule.seasons[0];
+        assert_eq!(season.show_id, 10);
+        assert_eq!(season.title, "Space Show");
+        assert_eq!(season.season_number, 2);
+        assert_eq!(
+            season.description,
+            Some(String::from("Ships fly to space."))
+        );
+        assert_eq!(
+            season.image_url,
+            Some(String::from("https://img/original/1.jpg"))
+        );
+        assert_eq!(season.host, Some(String::from("Netflix")));
+        assert_eq!(
+            season.airstamp,
+            Some(Utc.with_ymd_and_hms(2023, 11, 2, 1, 0, 0).unwrap())
+        );
+        assert_eq!(season.airtime, Some(String::from("21:00")));
+    }
+
+    #[test]
+    fn test_description_without_paragraph() {
+        let show = |summary: Option<&str>| NewRawShow {
+            id: 1,
+            url: String::from("url"),
+            name: String::from("Space Show"),
+            language: None,
+            genres: vec![],
+            image: None,
+            summary: summary.map(String::from),
+            web_channel: None,
+        };
+        assert_eq!(
+            show(Some("<p>Ships <b>fly</b>.</p><p>More.</p>")).description(),
+            Some(String::from("Ships fly."))
+        );
+        assert_eq!(
+            show(Some("Ships fly <i>far</i>.")).description(),
+            Some(String::from("Ships fly far."))
+        );
+        assert_eq!(show(Some("")).description(), None);
+        assert_eq!(show(None).description(), None);
+    }
+
+    #[test]
+    fn test_params() {
+        let target_genres = vec![String::from("Fantasy")];
+        let target_date = NaiveDate::from_ymd_opt(2023, 11, 2).unwrap();
+        let tv_maze = TvMaze::new(target_date, &target_genres);
+        assert_eq!(
+            tv_maze.params(),
+            vec![(String::from("date"), String::from("2023-11-02"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_source() {
+        let file_path = std::env::temp_dir().join("tv_series_bot_schedule.json");
+        fs::write(&file_path, SCHEDULE).unwrap();
+        let target_genres = vec![String::from("Science-Fiction")];
+        let source = TvMazeFile::open(file_path.to_str().unwrap(), &target_genres).unwrap();
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let seasons = source.seasons(date).await.unwrap().seasons;
+        assert_eq!(seasons.len(), 1);
+        assert_eq!(seasons[0].title, "Space Show");
+        assert!(TvMazeFile::open("/nonexistent/schedule.json", &target_genres).is_err());
+    }
+
+    #[test]
+    fn test_get_data_invalid_json() {
+        let target_genres = vec![String::from("Fantasy")];
+        let tv_maze = TvMaze::new(Utc::now().date_naive(), &target_genres);
+        assert!(tv_maze.get_data("<html></html>").is_err());
+    }
+}
@@ -1,25 +1,340 @@
+use crate::apis::SeasonData;
+use crate::daemon::Scheduler;
+use crate::error::BotError;
+use crate::locale::{Locale, DEFAULT_LOCALE};
+use crate::mastodon::Visibility;
+use crate::mastodon_instance::InstanceLimits;
+use crate::template::PostTemplate;
+use chrono::NaiveTime;
+use chrono_tz::Tz;
+use reqwest::header::HeaderValue;
 use serde_derive::Deserialize;
-use std::error::Error;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
 use toml;
 
+// Posting history when database_url is not set, next to the config file
+pub const DEFAULT_DATABASE_FILE: &str = "history.sqlite";
+
+fn default_locale() -> String {
+    DEFAULT_LOCALE.to_string()
+}
+
+fn default_max_post_len() -> i32 {
+    500
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_instance_cache_ttl() -> u64 {
+    86400
+}
+
+fn default_timezone() -> String {
+    String::from("UTC")
+}
+
+fn default_download_workers() -> usize {
+    4
+}
+
 #[derive(Deserialize, Debug)]
 pub struct MastodonConfig {
+    #[serde(alias = "mastodon_token")]
     pub token: String,
+    #[serde(alias = "mastodon_url")]
     pub url: String,
+    #[serde(alias = "mastodon_image_api_url")]
     pub image_api_url: String,
+    // used when the limits cannot be read from the instance
+    #[serde(default = "default_max_post_len")]
     pub max_post_len: i32,
+    // read the limits from /api/v2/instance at the start of every run
+    #[serde(default = "default_true")]
+    pub detect_limits: bool,
+    // seconds the detected limits are cached on disk
+    #[serde(default = "default_instance_cache_ttl")]
+    pub instance_cache_ttl: u64,
+    // post long descriptions as a thread of replies instead of cutting them
+    #[serde(default)]
+    pub thread: bool,
+    #[serde(default)]
+    pub visibility: Visibility,
+    // ISO 639-1 code of the posts, by default the language of the show
+    pub language: Option<String>,
+    // content warning of every post
+    pub spoiler_text: Option<String>,
+    // mark the images of every post as sensitive
+    #[serde(default)]
+    pub sensitive: bool,
+    // content warnings for some genres or description keywords
+    #[serde(default)]
+    pub content_warnings: Vec<ContentWarningRule>,
+    // detected limits, refreshed by the pipeline at the start of every run
+    #[serde(skip)]
+    instance_limits: RwLock<Option<InstanceLimits>>,
+    // post layout, see template::DEFAULT_TEMPLATE
+    pub template: Option<String>,
+    // language of the post labels and dates
+    #[serde(default = "default_locale")]
+    pub locale: String,
+    #[serde(skip)]
+    pub translations: Locale,
+    // global timezone, set by Config::new
+    #[serde(skip)]
+    pub tz: Tz,
 }
 impl MastodonConfig {
-    pub fn new(config_file_content: &str) -> Result<MastodonConfig, Box<dyn Error>> {
+    pub fn new(config_file_content: &str) -> Result<MastodonConfig, BotError> {
         let config: MastodonConfig = toml::from_str(config_file_content)?;
         Ok(config)
     }
+
+    // Value of the Authorization header, a token with e.g. a pasted newline is refused
+    pub fn authorization(&self) -> Result<HeaderValue, BotError> {
+        HeaderValue::from_str(&format!("Bearer {}", self.token)).map_err(|_| {
+            BotError::Config(String::from(
+                "mastodon token contains characters that are not allowed in an http header",
+            ))
+        })
+    }
+
+    // The detected limits of the instance, or the ones from the config
+    pub fn limits(&self) -> InstanceLimits {
+        self.instance_limits
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+            .unwrap_or_else(|| InstanceLimits::from_config(self))
+    }
+
+    pub fn set_instance_limits(&self, limits: InstanceLimits) {
+        *self
+            .instance_limits
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(limits);
+    }
+}
+
+// [[mastodon.content_warnings]], matches a season with one of the genres
+// or with one of the keywords in its description (case-insensitive, whole
+// words, so "war" does not match "award")
+#[derive(Deserialize, Debug, Clone)]
+pub struct ContentWarningRule {
+    #[serde(default)]
+    pub genres: Vec<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    pub spoiler_text: Option<String>,
+    #[serde(default)]
+    pub sensitive: bool,
+}
+
+impl ContentWarningRule {
+    pub fn matches(&self, data: &SeasonData) -> bool {
+        let genre_matches = self.genres.iter().any(|genre| {
+            data.genres
+                .iter()
+                .any(|season_genre| season_genre.eq_ignore_ascii_case(genre))
+        });
+        let description = words(data.description.as_deref().unwrap_or_default());
+        let keyword_matches = self.keywords.iter().any(|keyword| {
+            let keyword = words(keyword);
+            !keyword.is_empty()
+                && description
+                    .windows(keyword.len())
+                    .any(|phrase| phrase == keyword.as_slice())
+        });
+        genre_matches || keyword_matches
+    }
+}
+
+// Lowercase words of the text, split on everything that is not a letter or a digit
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(String::from)
+        .collect()
 }
 
 #[derive(Deserialize, Debug)]
 pub struct TelegramConfig {
+    #[serde(alias = "telegram_token")]
     pub token: String,
+    #[serde(alias = "telegram_chat_id")]
     pub chat_id: String,
+    // post layout, see template::DEFAULT_TEMPLATE
+    pub template: Option<String>,
+    // language of the post labels and dates
+    #[serde(default = "default_locale")]
+    pub locale: String,
+    #[serde(skip)]
+    pub translations: Locale,
+    // global timezone, set by Config::new
+    #[serde(skip)]
+    pub tz: Tz,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct DaemonConfig {
+    // cron expressions with seconds, e.g. "0 0 15 * * *" for every day at 15:00
+    #[serde(default)]
+    pub schedules: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QueueConfig {
+    // local times ("HH:MM") between which queued posts are published
+    pub window_start: Option<String>,
+    pub window_end: Option<String>,
+    // minimal number of seconds between two posts
+    pub min_interval: Option<u64>,
+}
+
+impl QueueConfig {
+    pub fn window(&self) -> Option<(NaiveTime, NaiveTime)> {
+        let window_start = NaiveTime::parse_from_str(self.window_start.as_ref()?, "%H:%M").ok()?;
+        let window_end = NaiveTime::parse_from_str(self.window_end.as_ref()?, "%H:%M").ok()?;
+        Some((window_start, window_end))
+    }
+
+    fn validate(&self) -> Result<(), BotError> {
+        match (&self.window_start, &self.window_end) {
+            (None, None) if self.min_interval.is_none() => Err(BotError::Config(String::from(
+                "queue needs window_start and window_end or min_interval",
+            ))),
+            (None, None) => Ok(()),
+            (Some(_), Some(_)) => match self.window() {
+                Some((window_start, window_end)) if window_start < window_end => Ok(()),
+                Some(_) => Err(BotError::Config(String::from(
+                    "queue window_start must be before window_end",
+                ))),
+                None => Err(BotError::Config(String::from(
+                    "queue window_start and window_end must be in HH:MM format",
+                ))),
+            },
+            _ => Err(BotError::Config(String::from(
+                "queue needs both window_start and window_end",
+            ))),
+        }
+    }
+}
+
+// Retry policy shared by all http requests
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RetryConfig {
+    // total number of attempts, 1 disables retries
+    pub attempts: u32,
+    // delay before the first retry in seconds, doubled on every next one
+    pub initial_delay: f64,
+    pub max_delay: f64,
+    // http statuses worth another attempt, network errors are always retried
+    pub retry_statuses: Vec<u16>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            initial_delay: 1.0,
+            max_delay: 60.0,
+            retry_statuses: vec![408, 429, 500, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryConfig {
+    // Exponential backoff with jitter: a random delay between half and the full backoff
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31) as i32;
+        let delay = (self.initial_delay * 2f64.powi(exponent)).min(self.max_delay);
+        let jitter = fastrand::f64();
+        Duration::from_secs_f64((delay / 2.0 + delay / 2.0 * jitter).max(0.0))
+    }
+
+    pub fn is_retryable_status(&self, status: u16) -> bool {
+        self.retry_statuses.contains(&status)
+    }
+}
+
+// Shared http client: timeouts in seconds, proxy, extra CA and user agent
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HttpConfig {
+    // identifies the bot and a contact address, as the tvmaze api asks
+    pub user_agent: String,
+    // establishing a connection, for every request
+    pub connect_timeout: f64,
+    // whole request including the response body, the http client has no
+    // timeout for a connection that stalls. tvmaze schedule and mastodon/telegram apis
+    pub api_total_timeout: f64,
+    pub download_total_timeout: f64,
+    pub upload_total_timeout: f64,
+    // http://, https://, socks5:// or socks5h:// url used for every request
+    pub proxy: Option<String>,
+    // PEM file with certificates trusted in addition to the system ones
+    pub ca_bundle: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: format!(
+                "tv_series_bot/{} (+https://github.com/dmitriiweb/mastodon-new-tv-series-bot)",
+                env!("CARGO_PKG_VERSION")
+            ),
+            connect_timeout: 10.0,
+            api_total_timeout: 30.0,
+            download_total_timeout: 60.0,
+            upload_total_timeout: 120.0,
+            proxy: None,
+            ca_bundle: None,
+        }
+    }
+}
+
+impl HttpConfig {
+    // The default user agent has no contact address of the operator
+    pub fn uses_default_user_agent(&self) -> bool {
+        self.user_agent == HttpConfig::default().user_agent
+    }
+
+    pub fn validate(&self) -> Result<(), BotError> {
+        if self.user_agent.trim().is_empty() {
+            return Err(BotError::Config(String::from(
+                "[http] user_agent must not be empty",
+            )));
+        }
+        let timeouts = [
+            ("connect_timeout", self.connect_timeout),
+            ("api_total_timeout", self.api_total_timeout),
+            ("download_total_timeout", self.download_total_timeout),
+            ("upload_total_timeout", self.upload_total_timeout),
+        ];
+        for (name, timeout) in timeouts {
+            if !timeout.is_finite() || timeout <= 0.0 {
+                return Err(BotError::Config(format!(
+                    "[http] {} must be a positive number of seconds",
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Prometheus metrics, both outputs are optional
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MetricsConfig {
+    // address of the /metrics endpoint of the daemon, e.g. 127.0.0.1:9185
+    pub listen: Option<String>,
+    // node_exporter textfile, rewritten at the end of every run
+    pub textfile: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -27,13 +342,116 @@ pub struct Config {
     pub target_genres: Vec<String>,
     pub send_to: Vec<String>,
     pub image_dir: String,
+    // sqlite database with the posting history, DEFAULT_DATABASE_FILE by default
+    #[serde(default)]
+    pub database_url: String,
+    // JSON summary of the last run, for the monitoring
+    pub summary_file: Option<String>,
+    // cache of the detected mastodon instance limits,
+    // `<database_url>.mastodon-instance.json` by default when database_url is a file path
+    pub instance_cache_file: Option<String>,
+    // only one instance at a time may use the posting history, `<database_url>.lock` by default
+    pub lock_file: Option<String>,
+    // how many past days to check for seasons missed by previous runs
+    #[serde(default)]
+    pub lookback_days: u32,
+    // directory with extra `<locale>.toml` translation files
+    pub locales_dir: Option<String>,
+    // IANA timezone for the schedule date and the dates in posts
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(skip)]
+    pub tz: Tz,
+    // how many images (and schedule days) are downloaded at the same time
+    #[serde(default = "default_download_workers")]
+    pub download_workers: usize,
     pub mastodon: MastodonConfig,
     pub telegram: TelegramConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    // spread the posts over time instead of publishing them all at once
+    pub queue: Option<QueueConfig>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
 }
 
 impl Config {
-    pub fn new(config_file_content: &str) -> Result<Config, Box<dyn Error>> {
-        let config: Config = toml::from_str(config_file_content)?;
+    // Cache of the mastodon instance limits, next to the posting history unless
+    // it is set. None for an in-memory or `file:` database, there is no file to put it next to
+    pub fn instance_cache_file(&self) -> Option<String> {
+        if let Some(instance_cache_file) = &self.instance_cache_file {
+            return Some(instance_cache_file.clone());
+        }
+        let database_url = self.database_url.as_str();
+        if database_url == ":memory:" || database_url.starts_with("file:") {
+            return None;
+        }
+        Some(format!("{}.mastodon-instance.json", database_url))
+    }
+
+    pub fn lock_file(&self) -> String {
+        self.lock_file
+            .clone()
+            .unwrap_or_else(|| format!("{}.lock", self.database_url))
+    }
+
+    pub fn new(config_file_content: &str) -> Result<Config, BotError> {
+        Self::with_base_dir(config_file_content, Path::new(""))
+    }
+
+    // base_dir is the directory of the config file, the default database is put there
+    pub fn with_base_dir(config_file_content: &str, base_dir: &Path) -> Result<Config, BotError> {
+        let mut config: Config = toml::from_str(config_file_content)?;
+        if config.database_url.is_empty() {
+            config.database_url = base_dir.join(DEFAULT_DATABASE_FILE).display().to_string();
+        }
+        config.tz = config
+            .timezone
+            .parse()
+            .map_err(|err| BotError::Config(format!("timezone {}: {}", config.timezone, err)))?;
+        config.mastodon.tz = config.tz;
+        config.telegram.tz = config.tz;
+        let locales_dir = config.locales_dir.as_deref();
+        config.mastodon.translations = Locale::load(&config.mastodon.locale, locales_dir)?;
+        config.telegram.translations = Locale::load(&config.telegram.locale, locales_dir)?;
+        PostTemplate::new(
+            "mastodon",
+            config.mastodon.template.as_deref(),
+            &config.mastodon.translations,
+            config.tz,
+        )?;
+        PostTemplate::new(
+            "telegram",
+            config.telegram.template.as_deref(),
+            &config.telegram.translations,
+            config.tz,
+        )?;
+        if let Some(queue) = &config.queue {
+            queue.validate()?;
+        }
+        config.http.validate()?;
+        config.mastodon.authorization()?;
+        if let Some(language) = &config.mastodon.language {
+            let is_code = language.len() == 2 && language.chars().all(|c| c.is_ascii_lowercase());
+            if !is_code {
+                return Err(BotError::Config(format!(
+                    "mastodon language {} must be an ISO 639-1 code like \"en\"",
+                    language
+                )));
+            }
+        }
+        if config.download_workers == 0 {
+            return Err(BotError::Config(String::from(
+                "download_workers must be at least 1",
+            )));
+        }
+        if !config.daemon.schedules.is_empty() {
+            Scheduler::new(&config.daemon.schedules, config.tz)?;
+        }
         Ok(config)
     }
 }
@@ -50,6 +468,8 @@ mod tests {
             
             target_genres = ["Fantasy", "Science-Fiction"]
             image_dir = "/path/to/images/dir"
+            database_url = "/path/to/history.sqlite"
+            timezone = "Europe/Berlin"
             
             [mastodon]
             token = "mastodon token"
@@ -61,6 +481,20 @@ mod tests {
             [telegram]
             token = "telegram token"
             chat_id = "telegram chat id"
+            locale = "de"
+
+            [daemon]
+            schedules = ["0 0 9 * * *", "0 0 21 * * *"]
+
+            [queue]
+            window_start = "09:00"
+            window_end = "21:00"
+
+            [retry]
+            attempts = 5
+
+            [http]
+            proxy = "socks5h://proxy.internal:1080"
         "#,
         );
         let config = Config::new(&toml_string).unwrap();
@@ -68,12 +502,244 @@ mod tests {
         assert_eq!(config.mastodon.token, "mastodon token");
         assert_eq!(config.mastodon.url, "https://your.mastodon.instance");
         assert_eq!(config.image_dir, "/path/to/images/dir");
+        assert_eq!(config.database_url, "/path/to/history.sqlite");
+        assert_eq!(config.lookback_days, 0);
+        assert_eq!(config.tz, chrono_tz::Europe::Berlin);
+        assert_eq!(config.telegram.tz, chrono_tz::Europe::Berlin);
         assert_eq!(config.mastodon.max_post_len, 500);
         assert_eq!(
             config.mastodon.image_api_url,
             "https://your.mastodon.instance/api/v2/media"
         );
         assert_eq!(config.telegram.token, "telegram token");
+        assert_eq!(config.mastodon.locale, "en");
+        assert_eq!(config.telegram.locale, "de");
+        assert_eq!(config.telegram.translations.labels["season"], "Staffel");
+        assert_eq!(config.daemon.schedules, vec!["0 0 9 * * *", "0 0 21 * * *"]);
+        assert_eq!(config.retry.attempts, 5);
+        assert_eq!(config.download_workers, 4);
+        assert_eq!(config.lock_file(), "/path/to/history.sqlite.lock");
+        assert_eq!(
+            config.instance_cache_file().as_deref(),
+            Some("/path/to/history.sqlite.mastodon-instance.json")
+        );
+        assert_eq!(config.http.api_total_timeout, 30.0);
+        assert_eq!(
+            config.http.proxy.as_deref(),
+            Some("socks5h://proxy.internal:1080")
+        );
+        assert!(config.http.user_agent.starts_with("tv_series_bot/"));
+        assert!(config.http.uses_default_user_agent());
+        assert_eq!(config.retry.max_delay, 60.0);
+        let (window_start, window_end) = config.queue.unwrap().window().unwrap();
+        assert_eq!(window_start, NaiveTime::from_hms_opt(9, 0, 0).unwrap());
+        assert_eq!(window_end, NaiveTime::from_hms_opt(21, 0, 0).unwrap());
+
+        let in_memory = toml_string.replace("/path/to/history.sqlite", ":memory:");
+        assert_eq!(Config::new(&in_memory).unwrap().instance_cache_file(), None);
+        let in_memory = in_memory.replace(
+            "timezone =",
+            "instance_cache_file = \"/var/cache/instance.json\"\n            timezone =",
+        );
+        assert_eq!(
+            Config::new(&in_memory)
+                .unwrap()
+                .instance_cache_file()
+                .as_deref(),
+            Some("/var/cache/instance.json")
+        );
+    }
+
+    #[test]
+    fn test_invalid_template() {
+        let toml_string = String::from(
+            r#"
+            send_to = ["telegram"]
+            target_genres = ["Fantasy"]
+            image_dir = "/path/to/images/dir"
+            database_url = "/path/to/history.sqlite"
+
+            [mastodon]
+            token = "mastodon token"
+            url = "https://your.mastodon.instance"
+            image_api_url = "https://your.mastodon.instance/api/v2/media"
+            max_post_len = 500
+
+            [telegram]
+            token = "telegram token"
+            chat_id = "telegram chat id"
+            template = """{{ title }}
+            {{ title | unknown_filter }}
+            """
+        "#,
+        );
+        let err = Config::new(&toml_string).err().unwrap();
+        assert!(err.to_string().starts_with("[telegram] template, line 2"));
+    }
+
+    #[test]
+    fn test_invalid_timezone() {
+        let toml_string = String::from(
+            r#"
+            send_to = ["telegram"]
+            target_genres = ["Fantasy"]
+            image_dir = "/path/to/images/dir"
+            database_url = "/path/to/history.sqlite"
+            timezone = "Mars/Olympus_Mons"
+
+            [mastodon]
+            token = "mastodon token"
+            url = "https://your.mastodon.instance"
+            image_api_url = "https://your.mastodon.instance/api/v2/media"
+            max_post_len = 500
+
+            [telegram]
+            token = "telegram token"
+            chat_id = "telegram chat id"
+        "#,
+        );
+        let err = Config::new(&toml_string).err().unwrap();
+        assert!(err.to_string().starts_with("timezone Mars/Olympus_Mons"));
+    }
+
+    #[test]
+    fn test_invalid_mastodon_options() {
+        let config_string = |mastodon_options: &str| {
+            format!(
+                r#"
+            send_to = ["mastodon"]
+            target_genres = ["Fantasy"]
+            image_dir = "/path/to/images/dir"
+            database_url = "/path/to/history.sqlite"
+
+            [mastodon]
+            token = "mastodon token"
+            url = "https://your.mastodon.instance"
+            image_api_url = "https://your.mastodon.instance/api/v2/media"
+            {}
+
+            [telegram]
+            token = "telegram token"
+            chat_id = "telegram chat id"
+        "#,
+                mastodon_options
+            )
+        };
+        assert!(Config::new(&config_string("language = \"de\"")).is_ok());
+        let err = Config::new(&config_string("language = \"German\""))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("ISO 639-1"));
+        assert!(Config::new(&config_string("visibility = \"followers\"")).is_err());
+        let err = Config::new(&config_string("").replace("mastodon token", "token\\n"))
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("mastodon token"));
+    }
+
+    #[test]
+    fn test_invalid_queue() {
+        let queue = QueueConfig {
+            window_start: Some(String::from("21:00")),
+            window_end: Some(String::from("09:00")),
+            min_interval: None,
+        };
+        assert!(queue.validate().is_err());
+        let queue = QueueConfig {
+            window_start: Some(String::from("9am")),
+            window_end: Some(String::from("9pm")),
+            min_interval: None,
+        };
+        assert!(queue.validate().is_err());
+        let queue = QueueConfig {
+            window_start: None,
+            window_end: None,
+            min_interval: None,
+        };
+        assert!(queue.validate().is_err());
+        let queue = QueueConfig {
+            window_start: None,
+            window_end: None,
+            min_interval: Some(1800),
+        };
+        assert!(queue.validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_http() {
+        let http = HttpConfig {
+            upload_total_timeout: 0.0,
+            ..HttpConfig::default()
+        };
+        let err = http.validate().err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "[http] upload_total_timeout must be a positive number of seconds"
+        );
+        let http = HttpConfig {
+            user_agent: String::from(" "),
+            ..HttpConfig::default()
+        };
+        assert!(http.validate().is_err());
+        assert!(HttpConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let retry = RetryConfig::default();
+        for (attempt, max_delay) in [(1, 1.0), (2, 2.0), (3, 4.0), (10, 60.0)] {
+            let delay = retry.backoff(attempt).as_secs_f64();
+            assert!(delay >= max_delay / 2.0 && delay <= max_delay, "{}", delay);
+        }
+        assert!(retry.is_retryable_status(503));
+        assert!(!retry.is_retryable_status(422));
+    }
+
+    #[test]
+    fn test_default_database() {
+        let config = Config::new(include_str!("../config.toml.original")).unwrap();
+        assert_eq!(config.database_url, DEFAULT_DATABASE_FILE);
+        assert_eq!(config.mastodon.token, "mastodon token");
+        assert_eq!(config.telegram.chat_id, "telegram chat id");
+        let config = Config::with_base_dir(
+            include_str!("../config.toml.original"),
+            Path::new("/etc/tv_series_bot"),
+        )
+        .unwrap();
+        assert_eq!(config.database_url, "/etc/tv_series_bot/history.sqlite");
+    }
+
+    #[test]
+    fn test_content_warning_keywords() {
+        let rule = ContentWarningRule {
+            genres: vec![],
+            keywords: vec![
+                String::from("war"),
+                String::from("Gore"),
+                String::from("serial killer"),
+            ],
+            spoiler_text: None,
+            sensitive: false,
+        };
+        let season = |description: &str| SeasonData {
+            show_id: 1,
+            title: String::from("title"),
+            url: String::from("url"),
+            language: None,
+            description: Some(String::from(description)),
+            genres: vec![],
+            image_url: None,
+            season_number: 1,
+            host: None,
+            airstamp: None,
+            airtime: None,
+        };
+        assert!(rule.matches(&season("After the War, nothing is the same.")));
+        assert!(rule.matches(&season("Blood and gore!")));
+        assert!(rule.matches(&season("A Serial  Killer is on the loose")));
+        assert!(!rule.matches(&season("An award-winning software engineer")));
+        assert!(!rule.matches(&season("Gregory moves to the countryside")));
+        assert!(!rule.matches(&season("A serial about a killer")));
     }
 
     #[test]
@@ -0,0 +1,117 @@
+use crate::error::BotError;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// How often a sleeping daemon checks for SIGTERM/SIGINT
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Cron-style schedules of the daemon runs, in the configured timezone
+pub struct Scheduler {
+    schedules: Vec<Schedule>,
+    timezone: Tz,
+}
+
+impl Scheduler {
+    pub fn new(schedules: &[String], timezone: Tz) -> Result<Scheduler, BotError> {
+        if schedules.is_empty() {
+            return Err(BotError::Config(String::from(
+                "daemon needs at least one schedule",
+            )));
+        }
+        let mut parsed = vec![];
+        for schedule in schedules.iter() {
+            let parsed_schedule = Schedule::from_str(schedule).map_err(|err| {
+                BotError::Config(format!("daemon schedule \"{}\": {}", schedule, err))
+            })?;
+            parsed.push(parsed_schedule);
+        }
+        Ok(Scheduler {
+            schedules: parsed,
+            timezone,
+        })
+    }
+
+    // Earliest run of any schedule after the given time
+    pub fn next_run(&self, after: DateTime<Utc>) -> Option<DateTime<Tz>> {
+        let after = after.with_timezone(&self.timezone);
+        self.schedules
+            .iter()
+            .filter_map(|schedule| schedule.after(&after).next())
+            .min()
+    }
+}
+
+// Flag set by SIGTERM/SIGINT, checked between posts
+pub fn register_shutdown() -> Result<Arc<AtomicBool>, BotError> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        signal_hook::flag::register(signal, Arc::clone(&shutdown))
+            .map_err(|err| BotError::io("cannot register signal handler", err))?;
+    }
+    Ok(shutdown)
+}
+
+pub fn is_shutting_down(shutdown: &AtomicBool) -> bool {
+    shutdown.load(Ordering::Relaxed)
+}
+
+// Sleep for `duration`, waking up early on shutdown. Returns false on shutdown.
+pub async fn sleep(duration: Duration, shutdown: &AtomicBool) -> bool {
+    let mut remaining = duration;
+    while !remaining.is_zero() {
+        if is_shutting_down(shutdown) {
+            return false;
+        }
+        let step = remaining.min(SHUTDOWN_CHECK_INTERVAL);
+        tokio::time::sleep(step).await;
+        remaining -= step;
+    }
+    !is_shutting_down(shutdown)
+}
+
+// Sleep until the given time, waking up early on shutdown. Returns false on shutdown.
+pub async fn sleep_until(run_at: DateTime<Utc>, shutdown: &AtomicBool) -> bool {
+    let duration = (run_at - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+    sleep(duration, shutdown).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_next_run() {
+        let schedules = vec![String::from("0 0 9 * * *"), String::from("0 30 21 * * *")];
+        let scheduler = Scheduler::new(&schedules, chrono_tz::Europe::Berlin).unwrap();
+        // 10:00 in Berlin
+        let now = Utc.with_ymd_and_hms(2023, 11, 2, 9, 0, 0).unwrap();
+        let next_run = scheduler.next_run(now).unwrap();
+        assert_eq!(next_run.to_rfc3339(), "2023-11-02T21:30:00+01:00");
+        let next_run = scheduler.next_run(next_run.with_timezone(&Utc)).unwrap();
+        assert_eq!(next_run.to_rfc3339(), "2023-11-03T09:00:00+01:00");
+    }
+
+    #[test]
+    fn test_invalid_schedule() {
+        let schedules = vec![String::from("every day")];
+        let err = Scheduler::new(&schedules, chrono_tz::UTC).err().unwrap();
+        assert!(err.to_string().starts_with("daemon schedule \"every day\""));
+    }
+
+    #[test]
+    fn test_no_schedules() {
+        assert!(Scheduler::new(&[], chrono_tz::UTC).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sleep_on_shutdown() {
+        let shutdown = AtomicBool::new(true);
+        assert!(!sleep(Duration::from_secs(60), &shutdown).await);
+    }
+}
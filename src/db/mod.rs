@@ -0,0 +1,341 @@
+pub mod models;
+pub mod schema;
+
+use crate::apis::SeasonData;
+use crate::error::BotError;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use models::{NewPublishedPost, NewQueuedPost, PublishedPost, QueuedPost};
+use schema::{published_posts, queued_posts};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+// Local record of every season already announced, per channel,
+// and of the posts waiting in the posting queue
+pub struct PostHistory {
+    connection: SqliteConnection,
+}
+
+impl PostHistory {
+    // Open (or create) the sqlite database and bring its schema up to date
+    pub fn open(database_url: &str) -> Result<PostHistory, BotError> {
+        let mut connection = SqliteConnection::establish(database_url)?;
+        connection
+            .run_pending_migrations(MIGRATIONS)
+            .map_err(|err| BotError::Database(format!("migrations: {}", err)))?;
+        Ok(PostHistory { connection })
+    }
+
+    // For dry runs, which only read the published posts: the database is
+    // neither created nor migrated. A database without published posts, e.g.
+    // a missing one, is an empty history
+    pub fn open_read_only(database_url: &str) -> Result<PostHistory, BotError> {
+        if database_url == ":memory:" {
+            return PostHistory::open(database_url);
+        }
+        let read_only_url = match database_url.strip_prefix("file:") {
+            Some(_) if database_url.contains('?') => format!("{}&mode=ro", database_url),
+            Some(_) => format!("{}?mode=ro", database_url),
+            None if !std::path::Path::new(database_url).exists() => {
+                return PostHistory::open(":memory:");
+            }
+            None => format!("file:{}?mode=ro", database_url),
+        };
+        let mut connection = SqliteConnection::establish(&read_only_url)?;
+        // published_posts is created by the first migration and never changed,
+        // so an outdated schema can still be read
+        let readable = published_posts::table
+            .count()
+            .get_result::<i64>(&mut connection)
+            .is_ok();
+        if !readable {
+            return PostHistory::open(":memory:");
+        }
+        Ok(PostHistory { connection })
+    }
+
+    pub fn is_published(&mut self, season: &SeasonData, channel: &str) -> Result<bool, BotError> {
+        let post = self.find(season, channel)?;
+        Ok(post.is_some())
+    }
+
+    pub fn find(
+        &mut self,
+        season: &SeasonData,
+        channel: &str,
+    ) -> Result<Option<PublishedPost>, BotError> {
+        let post = published_posts::table
+            .filter(published_posts::show_id.eq(season.show_id))
+            .filter(published_posts::season_number.eq(season.season_number))
+            .filter(published_posts::channel.eq(channel))
+            .select(PublishedPost::as_select())
+            .first(&mut self.connection)
+            .optional()?;
+        Ok(post)
+    }
+
+    pub fn record(
+        &mut self,
+        season: &SeasonData,
+        channel: &str,
+        post_id: &str,
+    ) -> Result<(), BotError> {
+        insert_published(&mut self.connection, season, channel, post_id)?;
+        Ok(())
+    }
+
+    pub fn is_queued(&mut self, season: &SeasonData, channel: &str) -> Result<bool, BotError> {
+        // given up posts count as queued, so that they are not queued again
+        let count: i64 = queued_posts::table
+            .filter(queued_posts::show_id.eq(season.show_id))
+            .filter(queued_posts::season_number.eq(season.season_number))
+            .filter(queued_posts::channel.eq(channel))
+            .count()
+            .get_result(&mut self.connection)?;
+        Ok(count > 0)
+    }
+
+    pub fn enqueue(
+        &mut self,
+        season: &SeasonData,
+        channel: &str,
+        publish_at: DateTime<Utc>,
+    ) -> Result<(), BotError> {
+        let new_post = NewQueuedPost {
+            show_id: season.show_id,
+            season_number: season.season_number,
+            channel,
+            season_data: serde_json::to_string(season)
+                .map_err(|err| BotError::Parse(format!("season data: {}", err)))?,
+            publish_at: publish_at.naive_utc(),
+        };
+        diesel::insert_into(queued_posts::table)
+            .values(&new_post)
+            .execute(&mut self.connection)?;
+        Ok(())
+    }
+
+    // Queued posts with publish time before `now`, oldest first
+    pub fn due_posts(&mut self, now: DateTime<Utc>) -> Result<Vec<QueuedPost>, BotError> {
+        let posts = queued_posts::table
+            .filter(queued_posts::publish_at.le(now.naive_utc()))
+            .filter(queued_posts::failed_at.is_null())
+            .order((queued_posts::publish_at, queued_posts::id))
+            .select(QueuedPost::as_select())
+            .load(&mut self.connection)?;
+        Ok(posts)
+    }
+
+    pub fn next_queued_at(&mut self) -> Result<Option<DateTime<Utc>>, BotError> {
+        let publish_at = queued_posts::table
+            .filter(queued_posts::failed_at.is_null())
+            .select(diesel::dsl::min(queued_posts::publish_at))
+            .first::<Option<chrono::NaiveDateTime>>(&mut self.connection)?;
+        Ok(publish_at.map(|publish_at| publish_at.and_utc()))
+    }
+
+    pub fn last_queued_at(&mut self) -> Result<Option<DateTime<Utc>>, BotError> {
+        let publish_at = queued_posts::table
+            .filter(queued_posts::failed_at.is_null())
+            .select(diesel::dsl::max(queued_posts::publish_at))
+            .first::<Option<chrono::NaiveDateTime>>(&mut self.connection)?;
+        Ok(publish_at.map(|publish_at| publish_at.and_utc()))
+    }
+
+    // Try a failed post again at `publish_at`
+    pub fn postpone(
+        &mut self,
+        queued: &QueuedPost,
+        publish_at: DateTime<Utc>,
+    ) -> Result<(), BotError> {
+        diesel::update(queued_posts::table.find(queued.id))
+            .set((
+                queued_posts::publish_at.eq(publish_at.naive_utc()),
+                queued_posts::attempts.eq(queued.attempts + 1),
+            ))
+            .execute(&mut self.connection)?;
+        Ok(())
+    }
+
+    // Give up a post that cannot be published
+    pub fn fail_queued(&mut self, queued: &QueuedPost) -> Result<(), BotError> {
+        diesel::update(queued_posts::table.find(queued.id))
+            .set((
+                queued_posts::attempts.eq(queued.attempts + 1),
+                queued_posts::failed_at.eq(Some(Utc::now().naive_utc())),
+            ))
+            .execute(&mut self.connection)?;
+        Ok(())
+    }
+
+    // Move a queued post to the history once it is published
+    pub fn record_queued(&mut self, queued: &QueuedPost, post_id: &str) -> Result<(), BotError> {
+        let season = queued.season()?;
+        self.connection.transaction(|connection| {
+            insert_published(connection, &season, &queued.channel, post_id)?;
+            diesel::delete(queued_posts::table.find(queued.id)).execute(connection)?;
+            diesel::QueryResult::Ok(())
+        })?;
+        Ok(())
+    }
+}
+
+fn insert_published(
+    connection: &mut SqliteConnection,
+    season: &SeasonData,
+    channel: &str,
+    post_id: &str,
+) -> diesel::QueryResult<usize> {
+    let new_post = NewPublishedPost {
+        show_id: season.show_id,
+        season_number: season.season_number,
+        channel,
+        post_id,
+        published_at: Utc::now().naive_utc(),
+    };
+    diesel::insert_into(published_posts::table)
+        .values(&new_post)
+        .execute(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn season_data(show_id: i32, season_number: i32) -> SeasonData {
+        SeasonData {
+            show_id,
+            title: String::from("title"),
+            url: String::from("url"),
+            language: None,
+            description: None,
+            genres: vec![],
+            image_url: None,
+            season_number,
+            host: None,
+            airstamp: None,
+            airtime: None,
+        }
+    }
+
+    #[test]
+    fn test_new_season_is_not_published() {
+        let mut history = PostHistory::open(":memory:").unwrap();
+        let season = season_data(1, 2);
+        assert!(!history.is_published(&season, "mastodon").unwrap());
+    }
+
+    #[test]
+    fn test_record_is_per_channel() {
+        let mut history = PostHistory::open(":memory:").unwrap();
+        let season = season_data(1, 2);
+        history.record(&season, "mastodon", "109").unwrap();
+        assert!(history.is_published(&season, "mastodon").unwrap());
+        assert!(!history.is_published(&season, "telegram").unwrap());
+        assert!(!history
+            .is_published(&season_data(1, 3), "mastodon")
+            .unwrap());
+        let post = history.find(&season, "mastodon").unwrap().unwrap();
+        assert_eq!(post.post_id, "109");
+    }
+
+    #[test]
+    fn test_read_only_history() {
+        let path = std::env::temp_dir().join(format!(
+            "tv_series_bot_read_only_{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let database_url = path.to_str().unwrap();
+        let season = season_data(1, 2);
+        // a dry run before the first real run does not create the database
+        let mut history = PostHistory::open_read_only(database_url).unwrap();
+        assert!(!history.is_published(&season, "mastodon").unwrap());
+        assert!(!path.exists());
+        let mut history = PostHistory::open(database_url).unwrap();
+        history.record(&season, "mastodon", "109").unwrap();
+        // as if the bot was upgraded and has a new migration
+        diesel::sql_query("DELETE FROM __diesel_schema_migrations")
+            .execute(&mut history.connection)
+            .unwrap();
+        drop(history);
+        let mut history = PostHistory::open_read_only(database_url).unwrap();
+        assert!(history.is_published(&season, "mastodon").unwrap());
+        assert!(history.record(&season, "telegram", "110").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_record_twice_fails() {
+        let mut history = PostHistory::open(":memory:").unwrap();
+        let season = season_data(1, 2);
+        history.record(&season, "mastodon", "109").unwrap();
+        assert!(history.record(&season, "mastodon", "110").is_err());
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut history = PostHistory::open(":memory:").unwrap();
+        let now = Utc::now();
+        let later = now + chrono::Duration::hours(2);
+        history
+            .enqueue(&season_data(1, 2), "mastodon", now)
+            .unwrap();
+        history
+            .enqueue(&season_data(2, 1), "mastodon", later)
+            .unwrap();
+        assert!(history.is_queued(&season_data(1, 2), "mastodon").unwrap());
+        assert!(!history.is_queued(&season_data(1, 2), "telegram").unwrap());
+        assert!(history
+            .enqueue(&season_data(1, 2), "mastodon", later)
+            .is_err());
+        assert_eq!(
+            history.next_queued_at().unwrap().unwrap().timestamp(),
+            now.timestamp()
+        );
+        assert_eq!(
+            history.last_queued_at().unwrap().unwrap().timestamp(),
+            later.timestamp()
+        );
+
+        let due = history.due_posts(now).unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].season().unwrap().show_id, 1);
+
+        history.postpone(&due[0], later).unwrap();
+        assert!(history.due_posts(now).unwrap().is_empty());
+        let due = history.due_posts(later).unwrap();
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[0].attempts, 1);
+
+        history.record_queued(&due[0], "109").unwrap();
+        assert!(!history.is_queued(&season_data(1, 2), "mastodon").unwrap());
+        assert!(history
+            .is_published(&season_data(1, 2), "mastodon")
+            .unwrap());
+        assert_eq!(history.due_posts(later).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_failed_queued_post() {
+        let mut history = PostHistory::open(":memory:").unwrap();
+        let now = Utc::now();
+        history
+            .enqueue(&season_data(1, 2), "mastodon", now)
+            .unwrap();
+        let due = history.due_posts(now).unwrap();
+        history.fail_queued(&due[0]).unwrap();
+        assert!(history.due_posts(now).unwrap().is_empty());
+        assert!(history.next_queued_at().unwrap().is_none());
+        assert!(history.is_queued(&season_data(1, 2), "mastodon").unwrap());
+    }
+
+    #[test]
+    fn test_empty_queue() {
+        let mut history = PostHistory::open(":memory:").unwrap();
+        assert!(history.next_queued_at().unwrap().is_none());
+        assert!(history.last_queued_at().unwrap().is_none());
+    }
+}
@@ -0,0 +1,59 @@
+use super::schema::{published_posts, queued_posts};
+use crate::apis::SeasonData;
+use crate::error::BotError;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = published_posts)]
+pub struct PublishedPost {
+    pub id: i32,
+    pub show_id: i32,
+    pub season_number: i32,
+    pub channel: String,
+    pub post_id: String,
+    pub published_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = published_posts)]
+pub struct NewPublishedPost<'a> {
+    pub show_id: i32,
+    pub season_number: i32,
+    pub channel: &'a str,
+    pub post_id: &'a str,
+    pub published_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = queued_posts)]
+pub struct QueuedPost {
+    pub id: i32,
+    pub show_id: i32,
+    pub season_number: i32,
+    pub channel: String,
+    // SeasonData as json, so the post can be published without fetching tvmaze again
+    pub season_data: String,
+    pub publish_at: NaiveDateTime,
+    // failed publishing attempts
+    pub attempts: i32,
+    // set when the post is given up, it stays in the queue so that it is not queued again
+    pub failed_at: Option<NaiveDateTime>,
+}
+
+impl QueuedPost {
+    pub fn season(&self) -> Result<SeasonData, BotError> {
+        serde_json::from_str(&self.season_data)
+            .map_err(|err| BotError::Parse(format!("queued post {}: {}", self.id, err)))
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = queued_posts)]
+pub struct NewQueuedPost<'a> {
+    pub show_id: i32,
+    pub season_number: i32,
+    pub channel: &'a str,
+    pub season_data: String,
+    pub publish_at: NaiveDateTime,
+}
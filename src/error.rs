@@ -0,0 +1,197 @@
+use crate::locale::LocaleError;
+use crate::template::TemplateError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// Error returned by every module, only `main` decides whether it is fatal
+#[derive(Debug)]
+pub enum BotError {
+    // invalid config, template, locale or daemon schedule
+    Config(String),
+    // the request could not be sent or the response could not be read
+    Network {
+        endpoint: String,
+        source: reqwest::Error,
+    },
+    // non-2xx response that is not explained by the platform
+    HttpStatus {
+        endpoint: String,
+        status: u16,
+        body: String,
+    },
+    // unexpected api response, input file or stored data
+    Parse(String),
+    // the platform refused the post, e.g. mastodon 422 "Validation failed"
+    Platform {
+        platform: String,
+        status: Option<u16>,
+        message: String,
+    },
+    // with the file or the operation that failed
+    Io {
+        context: String,
+        source: io::Error,
+    },
+    Database(String),
+    // the lock file belongs to a running instance
+    AlreadyRunning {
+        path: String,
+        pid: u32,
+    },
+}
+
+impl BotError {
+    pub fn io(context: &str, source: io::Error) -> Self {
+        BotError::Io {
+            context: context.to_string(),
+            source,
+        }
+    }
+
+    // Sending the same request again cannot succeed, e.g. a post refused with 422
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            BotError::Config(_) => true,
+            BotError::Platform {
+                status: Some(status),
+                ..
+            }
+            | BotError::HttpStatus { status, .. } => {
+                (400..500).contains(status) && *status != 408 && *status != 429
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BotError::Config(message) => write!(f, "{}", message),
+            BotError::Network { endpoint, source } => {
+                write!(f, "request to {} failed: {}", endpoint, source)
+            }
+            BotError::HttpStatus {
+                endpoint,
+                status,
+                body,
+            } => {
+                let body: String = body.chars().take(200).collect();
+                write!(f, "{} returned HTTP {}: {}", endpoint, status, body)
+            }
+            BotError::Parse(message) => write!(f, "cannot parse {}", message),
+            BotError::Platform {
+                platform,
+                status: Some(status),
+                message,
+            } => write!(f, "{} HTTP {}: {}", platform, status, message),
+            BotError::Platform {
+                platform,
+                status: None,
+                message,
+            } => write!(f, "{}: {}", platform, message),
+            BotError::Io { context, source } => write!(f, "{}: {}", context, source),
+            BotError::Database(message) => write!(f, "database: {}", message),
+            BotError::AlreadyRunning { path, pid } => {
+                write!(f, "another instance (pid {}) holds the lock {}", pid, path)
+            }
+        }
+    }
+}
+
+impl Error for BotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BotError::Network { source, .. } => Some(source),
+            BotError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for BotError {
+    fn from(err: reqwest::Error) -> Self {
+        // only the last path segment, the telegram url contains the bot token
+        let endpoint = err
+            .url()
+            .and_then(|url| url.path_segments()?.next_back().map(|s| s.to_string()))
+            .unwrap_or_default();
+        BotError::Network {
+            endpoint,
+            source: err.without_url(),
+        }
+    }
+}
+
+impl From<toml::de::Error> for BotError {
+    fn from(err: toml::de::Error) -> Self {
+        BotError::Config(err.to_string())
+    }
+}
+
+impl From<LocaleError> for BotError {
+    fn from(err: LocaleError) -> Self {
+        BotError::Config(err.to_string())
+    }
+}
+
+impl From<TemplateError> for BotError {
+    fn from(err: TemplateError) -> Self {
+        BotError::Config(err.to_string())
+    }
+}
+
+impl From<diesel::result::Error> for BotError {
+    fn from(err: diesel::result::Error) -> Self {
+        BotError::Database(err.to_string())
+    }
+}
+
+impl From<diesel::ConnectionError> for BotError {
+    fn from(err: diesel::ConnectionError) -> Self {
+        BotError::Database(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_permanent() {
+        let platform_error = |status| BotError::Platform {
+            platform: String::from("mastodon"),
+            status,
+            message: String::new(),
+        };
+        assert!(platform_error(Some(422)).is_permanent());
+        assert!(!platform_error(Some(429)).is_permanent());
+        assert!(!platform_error(Some(503)).is_permanent());
+        assert!(!platform_error(None).is_permanent());
+        assert!(BotError::Config(String::from("unknown channel")).is_permanent());
+        assert!(!BotError::Database(String::from("locked")).is_permanent());
+    }
+
+    #[test]
+    fn test_display() {
+        let err = BotError::Platform {
+            platform: String::from("mastodon"),
+            status: Some(422),
+            message: String::from("Validation failed"),
+        };
+        assert_eq!(err.to_string(), "mastodon HTTP 422: Validation failed");
+        let err = BotError::HttpStatus {
+            endpoint: String::from("web"),
+            status: 502,
+            body: "x".repeat(300),
+        };
+        assert_eq!(
+            err.to_string(),
+            format!("web returned HTTP 502: {}", "x".repeat(200))
+        );
+        let err = BotError::io("config.toml", io::Error::from(io::ErrorKind::NotFound));
+        assert!(err.to_string().starts_with("config.toml: "));
+        assert!(err.source().is_some());
+    }
+}
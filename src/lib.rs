@@ -0,0 +1,32 @@
+// Announces new seasons of tv series: seasons come from a `Source`,
+// are rendered by a `Formatter` and published by every `Publisher`
+// of the `Registry`, see `pipeline::Pipeline`
+pub mod apis;
+pub mod config;
+pub mod daemon;
+pub mod db;
+pub mod error;
+pub mod locale;
+pub mod lock;
+pub mod logging;
+pub mod mastodon;
+pub mod mastodon_instance;
+pub mod mastodon_text;
+pub mod metrics;
+pub mod pipeline;
+pub mod preview;
+pub mod publisher;
+pub mod queue;
+pub mod registry;
+pub mod requests;
+pub mod summary;
+pub mod telegram;
+pub mod template;
+pub mod utils;
+
+pub use apis::{Schedule, SeasonData, Source};
+pub use error::BotError;
+pub use pipeline::{Pipeline, PublishOptions};
+pub use publisher::Publisher;
+pub use registry::Registry;
+pub use template::Formatter;
@@ -0,0 +1,227 @@
+use chrono::format::{Fixed, Item, StrftimeItems};
+use chrono::{DateTime, Datelike, TimeZone};
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+// Labels used by the default template
+const REQUIRED_LABELS: [&str; 5] = ["host", "when", "season", "language", "genres"];
+
+// Locales shipped with the binary, files in `locales_dir` take precedence
+const BUILTIN_LOCALES: [(&str, &str); 3] = [
+    ("en", include_str!("../locales/en.toml")),
+    ("de", include_str!("../locales/de.toml")),
+    ("es", include_str!("../locales/es.toml")),
+];
+
+#[derive(Debug)]
+pub struct LocaleError {
+    pub locale: String,
+    pub message: String,
+}
+
+impl fmt::Display for LocaleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "locale {}: {}", self.locale, self.message)
+    }
+}
+
+impl Error for LocaleError {}
+
+// Translations of the fixed post labels and the date format
+#[derive(Deserialize, Debug, Clone)]
+pub struct Locale {
+    pub not_available: String,
+    pub date_format: String,
+    pub months: Vec<String>,
+    pub labels: HashMap<String, String>,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOCALE, BUILTIN_LOCALES[0].1).expect("builtin locale is valid")
+    }
+}
+
+impl Locale {
+    pub fn new(name: &str, file_content: &str) -> Result<Locale, LocaleError> {
+        let locale: Locale = toml::from_str(file_content).map_err(|err| LocaleError {
+            locale: name.to_string(),
+            message: err.to_string(),
+        })?;
+        if locale.months.len() != 12 {
+            return Err(LocaleError {
+                locale: name.to_string(),
+                message: format!("expected 12 months, got {}", locale.months.len()),
+            });
+        }
+        if let Some(label) = REQUIRED_LABELS
+            .iter()
+            .find(|label| !locale.labels.contains_key(**label))
+        {
+            return Err(LocaleError {
+                locale: name.to_string(),
+                message: format!("missing label {:?} in [labels]", label),
+            });
+        }
+        if let Some(message) = date_format_error(&locale.date_format) {
+            return Err(LocaleError {
+                locale: name.to_string(),
+                message,
+            });
+        }
+        Ok(locale)
+    }
+
+    // Load `<locales_dir>/<name>.toml`, or one of the builtin locales
+    pub fn load(name: &str, locales_dir: Option<&str>) -> Result<Locale, LocaleError> {
+        if let Some(locales_dir) = locales_dir {
+            let file_path = Path::new(locales_dir).join(format!("{}.toml", name));
+            if file_path.exists() {
+                let file_content =
+                    std::fs::read_to_string(&file_path).map_err(|err| LocaleError {
+                        locale: name.to_string(),
+                        message: format!("cannot read {}: {}", file_path.display(), err),
+                    })?;
+                return Self::new(name, &file_content);
+            }
+        }
+        match BUILTIN_LOCALES.iter().find(|(locale, _)| *locale == name) {
+            Some((_, file_content)) => Self::new(name, file_content),
+            None => Err(LocaleError {
+                locale: name.to_string(),
+                message: String::from("unknown locale, add a translation file to locales_dir"),
+            }),
+        }
+    }
+
+    pub fn format_date<Tz: TimeZone>(&self, date: &DateTime<Tz>) -> String
+    where
+        Tz::Offset: fmt::Display,
+    {
+        let month = self.months[date.month0() as usize].as_str();
+        // %B becomes the translated month, the name is not parsed as a format
+        let items: Vec<Item> = StrftimeItems::new(&self.date_format)
+            .map(|item| match item {
+                Item::Fixed(Fixed::LongMonthName) => Item::Literal(month),
+                item => item,
+            })
+            .collect();
+        date.format_with_items(items.into_iter()).to_string()
+    }
+}
+
+// Invalid specifiers would make the formatting panic, and the names other
+// than %B (replaced with `months`) are only available in English
+fn date_format_error(date_format: &str) -> Option<String> {
+    for item in StrftimeItems::new(date_format) {
+        match item {
+            Item::Error => {
+                return Some(format!("invalid date_format {:?}", date_format));
+            }
+            Item::Fixed(
+                Fixed::ShortMonthName
+                | Fixed::ShortWeekdayName
+                | Fixed::LongWeekdayName
+                | Fixed::UpperAmPm
+                | Fixed::LowerAmPm,
+            ) => {
+                return Some(format!(
+                    "date_format {:?}: only %B is translated, the other names would be in English",
+                    date_format
+                ));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_builtin_locales() {
+        for (name, _) in BUILTIN_LOCALES.iter() {
+            let locale = Locale::load(name, None).unwrap();
+            for label in ["host", "when", "season", "language", "genres"] {
+                assert!(locale.labels.contains_key(label), "{} {}", name, label);
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_date() {
+        let date = Utc.with_ymd_and_hms(2023, 3, 2, 15, 0, 0).unwrap();
+        let de = Locale::load("de", None).unwrap();
+        assert_eq!(de.format_date(&date), "02. März 2023");
+        let es = Locale::load("es", None).unwrap();
+        assert_eq!(es.format_date(&date), "02 de marzo de 2023");
+        assert_eq!(Locale::default().format_date(&date), "02 March 2023");
+        let mut locale = Locale {
+            date_format: String::from("%%B %B"),
+            ..Locale::default()
+        };
+        locale.months[2] = String::from("100% %Y March");
+        assert_eq!(locale.format_date(&date), "%B 100% %Y March");
+    }
+
+    #[test]
+    fn test_load_from_locales_dir() {
+        let locales_dir = std::env::temp_dir().join("tv_series_bot_locales");
+        std::fs::create_dir_all(&locales_dir).unwrap();
+        let file_content = include_str!("../locales/en.toml").replace("N/A", "n.d.");
+        std::fs::write(locales_dir.join("it.toml"), file_content).unwrap();
+        let locale = Locale::load("it", locales_dir.to_str()).unwrap();
+        assert_eq!(locale.not_available, "n.d.");
+        // builtin locales are still available
+        assert!(Locale::load("de", locales_dir.to_str()).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_locale() {
+        assert!(Locale::load("xx", None).is_err());
+    }
+
+    #[test]
+    fn test_invalid_months() {
+        let file_content = r#"
+            not_available = "N/A"
+            date_format = "%d %B %Y"
+            months = ["January"]
+            [labels]
+        "#;
+        let err = Locale::new("en", file_content).err().unwrap();
+        assert_eq!(err.to_string(), "locale en: expected 12 months, got 1");
+    }
+
+    #[test]
+    fn test_missing_label() {
+        let file_content = include_str!("../locales/en.toml").replace("when = \"When\"\n", "");
+        let err = Locale::new("en", &file_content).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "locale en: missing label \"when\" in [labels]"
+        );
+    }
+
+    #[test]
+    fn test_invalid_date_format() {
+        let file_content = include_str!("../locales/en.toml");
+        let locale = |date_format: &str| {
+            let file_content = file_content.replace("%d %B %Y", date_format);
+            Locale::new("en", &file_content)
+        };
+        assert!(locale("%d.%m.%Y, %B").is_ok());
+        let err = locale("%d %Q %Y").err().unwrap();
+        assert!(err.to_string().contains("invalid date_format"));
+        assert!(locale("%A, %d %B").is_err());
+        assert!(locale("%d %b %Y").is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use crate::error::BotError;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, Write};
+use std::path::Path;
+
+// Lock file with the PID of the running instance, locked with flock for the
+// lifetime of the process. Keeps an overlapping cron run from posting the same
+// seasons twice. The kernel releases the lock when the process exits, so a lock
+// file left behind by a crash does not block the next run.
+#[derive(Debug)]
+pub struct InstanceLock {
+    // the lock is held while the file is open
+    _file: File,
+}
+
+impl InstanceLock {
+    // Fails with BotError::AlreadyRunning while another process holds the lock
+    pub fn acquire(path: &str) -> Result<InstanceLock, BotError> {
+        let path = Path::new(path);
+        let io_error = |err| BotError::io(&path.display().to_string(), err);
+        // not truncated before the lock is taken, the PID belongs to the lock owner
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(io_error)?;
+        if !try_lock(&file).map_err(io_error)? {
+            return Err(BotError::AlreadyRunning {
+                path: path.display().to_string(),
+                pid: lock_owner(path).unwrap_or_default(),
+            });
+        }
+        file.set_len(0).map_err(io_error)?;
+        file.rewind().map_err(io_error)?;
+        writeln!(file, "{}", std::process::id()).map_err(io_error)?;
+        // the file is not removed when the lock is released: a process that
+        // opened it just before could then lock a file that is no longer the lock
+        Ok(InstanceLock { _file: file })
+    }
+}
+
+fn lock_owner(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+// false when another process holds the lock
+#[cfg(unix)]
+fn try_lock(file: &File) -> std::io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if result == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(err),
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock(file: &File) -> std::io::Result<bool> {
+    use fs2::FileExt;
+    match file.try_lock_exclusive() {
+        Ok(()) => Ok(true),
+        Err(err) if err.raw_os_error() == fs2::lock_contended_error().raw_os_error() => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "tv_series_bot_{}_{}.lock",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path.display().to_string()
+    }
+
+    #[test]
+    fn test_second_instance_is_refused() {
+        let path = lock_path("second");
+        let lock = InstanceLock::acquire(&path).unwrap();
+        let err = InstanceLock::acquire(&path).err().unwrap();
+        assert!(matches!(err, BotError::AlreadyRunning { pid, .. } if pid == std::process::id()));
+        drop(lock);
+        assert!(InstanceLock::acquire(&path).is_ok());
+    }
+
+    #[test]
+    fn test_lock_file_left_behind_is_reused() {
+        let path = lock_path("stale");
+        // e.g. after a crash, nobody holds the lock
+        fs::write(&path, "99999999\n").unwrap();
+        let lock = InstanceLock::acquire(&path).unwrap();
+        assert_eq!(lock_owner(Path::new(&path)), Some(std::process::id()));
+        drop(lock);
+        fs::write(&path, "not a pid").unwrap();
+        assert!(InstanceLock::acquire(&path).is_ok());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::apis::SeasonData;
+use serde_derive::Serialize;
+use std::future::Future;
+use std::io::Write;
+
+tokio::task_local! {
+    // Fields added to every log event of the current run, season and channel
+    static CONTEXT: LogContext;
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    // env_logger lines
+    #[default]
+    Plain,
+    // one JSON object per line, with the fields of the LogContext
+    Json,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct LogContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub season: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+}
+
+impl LogContext {
+    // The fields of the enclosing scope, empty outside of any scope
+    pub fn current() -> LogContext {
+        CONTEXT
+            .try_with(|context| context.clone())
+            .unwrap_or_default()
+    }
+
+    fn with_season(mut self, season: &SeasonData, channel: Option<&str>) -> Self {
+        self.show_title = Some(season.title.clone());
+        self.show_id = Some(season.show_id);
+        self.season = Some(season.season_number);
+        if let Some(channel) = channel {
+            self.channel = Some(channel.to_string());
+        }
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    timestamp: String,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+    #[serde(flatten)]
+    context: LogContext,
+}
+
+// Random id that tells the events of one run apart from the other runs
+fn new_run_id() -> String {
+    format!("{:016x}", fastrand::u64(..))
+}
+
+// Run the future with a new run id
+pub async fn in_run<F: Future>(future: F) -> F::Output {
+    let context = LogContext {
+        run_id: Some(new_run_id()),
+        ..LogContext::default()
+    };
+    CONTEXT.scope(context, future).await
+}
+
+// Run the future with the fields of the season (and the channel)
+pub async fn for_season<F: Future>(
+    season: &SeasonData,
+    channel: Option<&str>,
+    future: F,
+) -> F::Output {
+    let context = LogContext::current().with_season(season, channel);
+    CONTEXT.scope(context, future).await
+}
+
+pub fn for_season_sync<R>(season: &SeasonData, channel: Option<&str>, f: impl FnOnce() -> R) -> R {
+    let context = LogContext::current().with_season(season, channel);
+    CONTEXT.sync_scope(context, f)
+}
+
+fn json_line(record: &log::Record) -> String {
+    let json_record = JsonRecord {
+        timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        level: record.level().as_str(),
+        target: record.target(),
+        message: record.args().to_string(),
+        context: LogContext::current(),
+    };
+    serde_json::to_string(&json_record).unwrap_or_default()
+}
+
+// env_logger with RUST_LOG filtering, in the given format
+pub fn init(log_format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if log_format == LogFormat::Json {
+        builder.format(|buf, record| writeln!(buf, "{}", json_line(record)));
+    }
+    builder.init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn season_data() -> SeasonData {
+        SeasonData {
+            show_id: 10,
+            title: String::from("Space Show"),
+            url: String::from("url"),
+            language: None,
+            description: None,
+            genres: vec![],
+            image_url: None,
+            season_number: 2,
+            host: None,
+            airstamp: None,
+            airtime: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_context_scopes() {
+        assert_eq!(LogContext::current(), LogContext::default());
+        let season = season_data();
+        let (run_context, season_context) = in_run(async {
+            let run_context = LogContext::current();
+            let season_context =
+                for_season(&season, Some("mastodon"), async { LogContext::current() }).await;
+            (run_context, season_context)
+        })
+        .await;
+        assert!(run_context.run_id.is_some());
+        assert!(run_context.show_id.is_none());
+        assert_eq!(season_context.run_id, run_context.run_id);
+        assert_eq!(season_context.show_id, Some(10));
+        assert_eq!(season_context.channel.as_deref(), Some("mastodon"));
+    }
+
+    #[test]
+    fn test_json_line() {
+        let season = season_data();
+        let line = for_season_sync(&season, Some("telegram"), || {
+            json_line(
+                &log::Record::builder()
+                    .args(format_args!("Cannot post"))
+                    .level(log::Level::Error)
+                    .target("tv_series_bot::pipeline")
+                    .build(),
+            )
+        });
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["level"], "ERROR");
+        assert_eq!(json["message"], "Cannot post");
+        assert_eq!(json["show_title"], "Space Show");
+        assert_eq!(json["season"], 2);
+        assert_eq!(json["channel"], "telegram");
+        assert!(json.get("run_id").is_none());
+    }
+}
@@ -1,150 +1,206 @@
-use chrono::format;
-use clap::Parser;
-use log::{error, warn};
-use mastodon::MastodonImageUploader;
-use std::error::Error;
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand};
+use log::error;
 use std::fs;
-use telegram::TelegramPost;
-
-pub mod apis;
-pub mod config;
-pub mod mastodon;
-pub mod requests;
-pub mod telegram;
-pub mod utils;
-
-use crate::apis::{SeasonData, TvMaze};
-use config::{Config, MastodonConfig, TelegramConfig};
-use requests::{download_file, FileDownload, RequestData};
+use std::path::Path;
+use std::time::Duration;
+use tv_series_bot::apis::TvMazeFile;
+use tv_series_bot::config::{self, Config};
+use tv_series_bot::db::PostHistory;
+use tv_series_bot::lock::InstanceLock;
+use tv_series_bot::logging::{self, LogFormat};
+use tv_series_bot::summary::RunStatus;
+use tv_series_bot::{
+    daemon, metrics, pipeline, requests, BotError, Pipeline, PublishOptions, Registry,
+};
 
 #[derive(Parser, Debug)]
 struct CliArguments {
     // path to the .toml config file
     #[arg(short, long)]
     config: String,
+    // render the posts without sending them
+    #[arg(long, global = true)]
+    dry_run: bool,
+    // "json" logs one object per line with run id, show and channel fields
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Plain)]
+    log_format: LogFormat,
+    // read the tvmaze schedule from a json file ("-" for stdin) instead of the api
+    #[arg(long, global = true)]
+    input: Option<String>,
+    // save every raw api response to this directory
+    #[arg(long, global = true)]
+    record_dir: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    // same as --dry-run for today's schedule
+    Preview,
+    // stay resident and run on the schedules from the [daemon] config section
+    Daemon,
+    // publish new seasons for every day from --from to --to (inclusive)
+    Backfill {
+        #[arg(long)]
+        from: NaiveDate,
+        #[arg(long)]
+        to: NaiveDate,
+        // publish only to this channel instead of every channel from send_to
+        #[arg(long)]
+        channel: Option<String>,
+        // seconds to wait between posts
+        #[arg(long, default_value_t = 60)]
+        interval: u64,
+    },
 }
 
-fn get_config(toml_file: String) -> Result<config::Config, Box<dyn Error>> {
-    let config_file_content = fs::read_to_string(toml_file)?;
-    let config: Config = Config::new(&config_file_content)?;
+// Exit codes of a run, a fatal error exits with EXIT_TOTAL_FAILURE too
+const EXIT_TOTAL_FAILURE: i32 = 1;
+// some posts or images failed, clap uses 2 for invalid arguments
+const EXIT_PARTIAL_FAILURE: i32 = 3;
+// another instance holds the lock file (EX_TEMPFAIL)
+const EXIT_ALREADY_RUNNING: i32 = 75;
+
+fn get_config(toml_file: String) -> Result<config::Config, BotError> {
+    let config_file_content =
+        fs::read_to_string(&toml_file).map_err(|err| BotError::io(&toml_file, err))?;
+    let config_dir = Path::new(&toml_file).parent().unwrap_or(Path::new(""));
+    let config: Config = Config::with_base_dir(&config_file_content, config_dir)?;
     Ok(config)
 }
 
-fn get_new_tv_shows(tv_maze: &apis::TvMaze) -> Vec<apis::SeasonData> {
-    let response = match requests::get(tv_maze) {
-        Ok(resp) => resp,
-        Err(err) => {
-            error!("Cannot get listing from api: {}", err);
-            std::process::exit(1);
+#[tokio::main]
+async fn main() {
+    let args = CliArguments::parse();
+    logging::init(args.log_format);
+    // a daemon gives every run its own id
+    logging::in_run(run(args)).await
+}
+
+async fn run(args: CliArguments) {
+    let config = get_config(args.config).unwrap_or_else(|err| {
+        log::error!("Problem parsing arguments: {}", err);
+        std::process::exit(1);
+    });
+    let dry_run = args.dry_run || matches!(args.command, Some(Command::Preview));
+    // dry runs publish nothing, so they can run next to a publishing instance
+    let instance_lock = if dry_run {
+        None
+    } else {
+        match InstanceLock::acquire(&config.lock_file()) {
+            Ok(instance_lock) => Some(instance_lock),
+            Err(err @ BotError::AlreadyRunning { .. }) => {
+                log::error!("Not starting: {}", err);
+                std::process::exit(EXIT_ALREADY_RUNNING);
+            }
+            Err(err) => {
+                log::error!("Cannot create lock file: {}", err);
+                std::process::exit(1);
+            }
         }
     };
-    match tv_maze.get_data(&response) {
-        Ok(seasons) => seasons,
-        Err(err) => {
-            error!("Cannot parse api response: {}", err);
+    // dry runs only read the posting history
+    let history = if dry_run {
+        PostHistory::open_read_only(&config.database_url)
+    } else {
+        PostHistory::open(&config.database_url)
+    };
+    let history = history.unwrap_or_else(|err| {
+        log::error!("Cannot open posting history: {}", err);
+        std::process::exit(1);
+    });
+    requests::set_retry_config(config.retry.clone());
+    if let Err(err) = requests::set_http_config(config.http.clone()) {
+        log::error!("Cannot set up the http client: {}", err);
+        std::process::exit(1);
+    }
+    if config.http.uses_default_user_agent() {
+        log::warn!(
+            "[http] user_agent is not set, please set one with a contact address as TVMaze asks"
+        );
+    }
+    if let Some(record_dir) = args.record_dir {
+        if let Err(err) = requests::set_record_dir(&record_dir) {
+            log::error!("Cannot record responses: {}", err);
             std::process::exit(1);
         }
     }
-}
-
-fn download_image(config: &Config, tv_maze: &TvMaze, new_season: &SeasonData) -> Option<String> {
-    let image_url = match new_season.image_url {
-        Some(ref url) => url.clone(),
-        _ => return None,
-    };
-    let download_image = FileDownload {
-        download_url: image_url.clone(),
-        save_folder: config.image_dir.clone(),
-        headers: tv_maze.headers().clone(),
-    };
-    let file_name = download_image.file_name();
-    match download_file(download_image) {
-        Ok(_) => (),
-        Err(err) => {
-            error!("Cannot download image {}: {}", image_url, err);
-            return None;
+    let mut registry = Registry::from_config(&config);
+    if let Some(Command::Backfill {
+        channel: Some(channel),
+        ..
+    }) = &args.command
+    {
+        if registry.publisher(channel).is_none() {
+            log::error!("Problem parsing arguments: unknown channel {}", channel);
+            std::process::exit(1);
         }
-    };
-    Some(file_name)
-}
-
-fn publish_mastodon_post(
-    config: &MastodonConfig,
-    new_season: &apis::SeasonData,
-    image_path: Option<String>,
-) {
-    // upload image if image_path is not None
-    let image_id = match image_path {
-        Some(image_path) => {
-            let image_uploader = MastodonImageUploader {
-                config,
-                image_path: &image_path,
-                image_title: &new_season.title,
-            };
-            match image_uploader.upload() {
-                Ok(id) => Some(id),
-                Err(err) => {
-                    error!("Cannot upload image {}: {}", image_path, err);
-                    None
-                }
+    }
+    if let Some(input) = args.input.as_deref() {
+        let source = TvMazeFile::open(input, &config.target_genres).unwrap_or_else(|err| {
+            log::error!("Cannot read input: {}", err);
+            std::process::exit(1);
+        });
+        registry.set_sources(vec![Box::new(source)]);
+    }
+    let pipeline = Pipeline::new(&config, registry, history);
+    let mut options = PublishOptions::new(&config);
+    options.dry_run = args.dry_run;
+    let is_daemon = matches!(args.command, Some(Command::Daemon));
+    let result = match args.command {
+        Some(Command::Backfill {
+            from,
+            to,
+            channel,
+            interval,
+        }) => {
+            if let Some(channel) = channel {
+                options.send_to = vec![channel];
             }
+            options.interval = Duration::from_secs(interval);
+            pipeline.backfill(from, to, &options).await
         }
-        None => None,
-    };
-    let mastodon_post = mastodon::MastodonPost::from_season_data(new_season, config, image_id);
-    let _ = match requests::post_multipart(&mastodon_post) {
-        Ok(r) => r,
-        Err(err) => {
-            error!("Cannot post to mastodon: {}", err);
-            return;
+        Some(Command::Preview) => {
+            options.dry_run = true;
+            pipeline.run(&options).await
         }
-    };
-}
-
-fn publish_telegram_post(
-    config: &TelegramConfig,
-    new_season: &apis::SeasonData,
-    image_path: Option<String>,
-) {
-    let telegram_post = TelegramPost::from_season_data(new_season, config, image_path.clone());
-    let result = match image_path {
-        Some(_) => requests::post_multipart(&telegram_post),
-        None => requests::post_json(&telegram_post),
-    };
-
-    let _ = match result {
-        Ok(r) => r,
-        Err(err) => {
-            error!("Cannot post to telegram: {}", err);
-            return;
+        Some(Command::Daemon) => {
+            options.shutdown = daemon::register_shutdown().unwrap_or_else(|err| {
+                log::error!("Cannot register signal handlers: {}", err);
+                std::process::exit(1);
+            });
+            if let Some(listen) = &config.metrics.listen {
+                let listener = metrics::bind(listen).await.unwrap_or_else(|err| {
+                    log::error!("Cannot serve metrics: {}", err);
+                    std::process::exit(1);
+                });
+                tokio::spawn(metrics::serve(listener));
+            }
+            pipeline.run_daemon(&options).await
         }
+        None => pipeline.run(&options).await,
     };
-}
-
-fn main() {
-    env_logger::init();
-    let args = CliArguments::parse();
-    let config = get_config(args.config).unwrap_or_else(|err| {
-        log::error!("Problem parsing arguments: {}", err);
-        std::process::exit(1);
-    });
-    let dt_now = chrono::Utc::now();
-    let tv_maze = apis::TvMaze::new(dt_now, &config.target_genres);
-    let new_shows = get_new_tv_shows(&tv_maze);
-    for new_season in new_shows.iter() {
-        let image: Option<String> = download_image(&config, &tv_maze, new_season);
-        let image_path: Option<String> = match image {
-            Some(image_name) => Some(format!("{}{}", config.image_dir, image_name)),
-            None => None,
-        };
-        for channel in config.send_to.iter() {
-            if channel == "mastodon" {
-                publish_mastodon_post(&config.mastodon, new_season, image_path.clone());
-            } else if channel == "telegram" {
-                publish_telegram_post(&config.telegram, new_season, image_path.clone())
-            } else {
-                warn!("Unknown SendTo param: {:?}", channel);
-            }
+    let mut summary = pipeline.take_summary();
+    if let Err(err) = result {
+        error!("{}", err);
+        summary.error = Some(err.to_string());
+    }
+    // the daemon reports every run itself
+    if !is_daemon || summary.error.is_some() {
+        // a dry run would overwrite the summary of the last real run
+        let summary_file = config.summary_file.as_deref().filter(|_| !options.dry_run);
+        summary.report(summary_file);
+        if !options.dry_run {
+            pipeline::write_metrics(&config);
         }
     }
+    // process::exit skips destructors
+    drop(instance_lock);
+    match summary.status() {
+        RunStatus::Success => {}
+        RunStatus::PartialFailure => std::process::exit(EXIT_PARTIAL_FAILURE),
+        RunStatus::TotalFailure => std::process::exit(EXIT_TOTAL_FAILURE),
+    }
 }
@@ -1,45 +1,176 @@
 use crate::apis;
 use crate::config::MastodonConfig;
-use crate::requests::{upload_file, FileUpload, RequestData};
+use crate::error::BotError;
+use crate::mastodon_instance::InstanceLimits;
+use crate::mastodon_text;
+use crate::preview::PostPreview;
+use crate::publisher::Publisher;
+use crate::requests::{post_multipart, upload_file, FileUpload, RequestData};
+use crate::template::{Formatter, PostTemplate};
 use crate::utils;
-use clap::builder::Str;
-use log::error;
+use async_trait::async_trait;
+use log::{error, warn};
 use reqwest::header::HeaderMap;
-use std::error::Error;
+use serde_derive::Deserialize;
 
 const DEFAULT_HASHTAGS: &str = "#tvseries #tvshows";
-const MASTODON_URL_LENGTH: i32 = 23;
+
+// Error reported by the mastodon api, e.g. 422 {"error": "Validation failed: Text too long"}
+fn mastodon_error(status: Option<u16>, body: &str) -> BotError {
+    let message = match serde_json::from_str::<ErrorResponse>(body) {
+        Ok(response) => match response.error_description {
+            Some(description) => format!("{}: {}", response.error, description),
+            None => response.error,
+        },
+        Err(_) => format!(
+            "unexpected response: {}",
+            body.chars().take(200).collect::<String>()
+        ),
+    };
+    BotError::Platform {
+        platform: String::from("mastodon"),
+        status,
+        message,
+    }
+}
+
+// Turn a non-2xx response into the error message sent by mastodon
+fn from_request_error(err: BotError) -> BotError {
+    match err {
+        BotError::HttpStatus { status, body, .. } => mastodon_error(Some(status), &body),
+        err => err,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+// Created status or uploaded media, only the id is used
+#[derive(Deserialize, Debug)]
+struct IdResponse {
+    id: String,
+}
+
+fn parse_id(response: &str) -> Result<String, BotError> {
+    serde_json::from_str::<IdResponse>(response)
+        .map(|response| response.id)
+        .map_err(|_| mastodon_error(None, response))
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    #[default]
+    Public,
+    Unlisted,
+    Private,
+    Direct,
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Unlisted => "unlisted",
+            Visibility::Private => "private",
+            Visibility::Direct => "direct",
+        }
+    }
+
+    // Replies of a thread stay out of the public timelines
+    fn for_reply(self) -> Self {
+        match self {
+            Visibility::Public => Visibility::Unlisted,
+            visibility => visibility,
+        }
+    }
+}
+
+// Status fields that depend on the show and the config but not on the text
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatusOptions {
+    pub visibility: Visibility,
+    // ISO 639-1 code
+    pub language: Option<String>,
+    // content warning shown instead of the text until it is opened
+    pub spoiler_text: Option<String>,
+    // hide the image behind a warning
+    pub sensitive: bool,
+}
+
+impl StatusOptions {
+    // The options from the config, with the content warnings of the rules
+    // matching the genres or the description of the season
+    pub fn for_season(config: &MastodonConfig, data: &apis::SeasonData) -> Self {
+        let language = match &config.language {
+            Some(language) => Some(language.clone()),
+            None => data
+                .language
+                .as_deref()
+                .and_then(utils::language_code)
+                .map(String::from),
+        };
+        let mut spoiler_texts: Vec<String> = config.spoiler_text.iter().cloned().collect();
+        let mut sensitive = config.sensitive;
+        for rule in config
+            .content_warnings
+            .iter()
+            .filter(|rule| rule.matches(data))
+        {
+            if let Some(spoiler_text) = &rule.spoiler_text {
+                if !spoiler_texts.contains(spoiler_text) {
+                    spoiler_texts.push(spoiler_text.clone());
+                }
+            }
+            sensitive |= rule.sensitive;
+        }
+        Self {
+            visibility: config.visibility,
+            language,
+            spoiler_text: (!spoiler_texts.is_empty()).then(|| spoiler_texts.join(", ")),
+            sensitive,
+        }
+    }
+
+    // Mastodon counts the content warning into the length of the status
+    fn limits(&self, config: &MastodonConfig) -> InstanceLimits {
+        let mut limits = config.limits();
+        let spoiler_length = self
+            .spoiler_text
+            .as_deref()
+            .map(|spoiler_text| spoiler_text.chars().count())
+            .unwrap_or_default();
+        limits.max_characters -= spoiler_length as i32;
+        limits
+    }
+}
 
 #[derive(Debug)]
 pub struct MastodonPost<'a> {
     pub post_text: String,
     pub config: &'a MastodonConfig,
     pub image_ids: Vec<String>,
+    // the previous status of the thread
+    pub in_reply_to_id: Option<String>,
+    pub options: StatusOptions,
+    // sent as Idempotency-Key, so that mastodon creates the status only once
+    // when a request is sent again, by a retry or by a new run of the same day
+    pub idempotency_key: String,
 }
 
 impl<'a> MastodonPost<'a> {
-    pub fn from_season_data(
+    // Post with the text from any formatter, trimmed to the length limit
+    pub fn new(
+        post: String,
         data: &apis::SeasonData,
         config: &'a MastodonConfig,
         image_id: Option<String>,
     ) -> Self {
-        let language = utils::hashtag_string_or_na(&data.language);
-        let genres = utils::get_genres(&data.genres);
-        let when = utils::get_when();
-        let description = utils::string_or_na(&data.description);
-        let host = utils::hashtag_string_or_na(&data.host);
-        let post = format!(
-            "{}\n\
-            {}\n\n\
-            Host: {}\n\
-            When: {}\n\
-            Season: {}\n\
-            Language: {}\n\
-            Genres: {}\n\n\
-            {}\n",
-            &data.title, &data.url, host, when, &data.season_number, language, genres, description,
-        );
-        let post_text = Self::trim_post(post, config.max_post_len, &data.url);
+        let options = StatusOptions::for_season(config, data);
+        let post_text = Self::trim_post(post, &options.limits(config));
         let image_ids = match image_id {
             Some(id) => vec![id],
             None => vec![],
@@ -48,25 +179,178 @@ impl<'a> MastodonPost<'a> {
             post_text,
             config,
             image_ids,
+            in_reply_to_id: None,
+            options,
+            idempotency_key: Self::idempotency_key(data, config, None),
+        }
+    }
+
+    // tv_series_bot-<show id>-<season>-<run date>, with the number of the
+    // status for the replies of a thread
+    fn idempotency_key(
+        data: &apis::SeasonData,
+        config: &MastodonConfig,
+        reply_number: Option<usize>,
+    ) -> String {
+        let key = format!(
+            "tv_series_bot-{}-{}-{}",
+            data.show_id,
+            data.season_number,
+            utils::today(&config.tz).format("%Y-%m-%d")
+        );
+        match reply_number {
+            Some(reply_number) => format!("{}-{}", key, reply_number),
+            None => key,
+        }
+    }
+
+    // The post as a thread if it is too long: the headline with the image,
+    // then the description in unlisted replies
+    pub fn thread(
+        post: String,
+        headline: Option<&str>,
+        data: &apis::SeasonData,
+        config: &'a MastodonConfig,
+        image_id: Option<String>,
+    ) -> Vec<Self> {
+        let options = StatusOptions::for_season(config, data);
+        let limits = options.limits(config);
+        Self::split_thread(&post, headline, &limits)
+            .into_iter()
+            .enumerate()
+            .map(|(i, post_text)| match i {
+                0 => Self {
+                    post_text,
+                    config,
+                    image_ids: image_id.clone().into_iter().collect(),
+                    in_reply_to_id: None,
+                    options: options.clone(),
+                    idempotency_key: Self::idempotency_key(data, config, None),
+                },
+                _ => Self {
+                    post_text,
+                    config,
+                    image_ids: vec![],
+                    in_reply_to_id: None,
+                    options: StatusOptions {
+                        visibility: options.visibility.for_reply(),
+                        sensitive: false,
+                        ..options.clone()
+                    },
+                    idempotency_key: Self::idempotency_key(data, config, Some(i + 1)),
+                },
+            })
+            .collect()
+    }
+
+    // Post length as counted by mastodon, where every url takes characters_reserved_per_url chars
+    // and the content warning counts too
+    pub fn text_length(&self) -> i32 {
+        let reserved_url_length = self.config.limits().characters_reserved_per_url.max(0) as usize;
+        let spoiler_length = self
+            .options
+            .spoiler_text
+            .as_deref()
+            .map(|spoiler_text| spoiler_text.chars().count())
+            .unwrap_or_default();
+        (mastodon_text::length(&self.post_text, reserved_url_length) + spoiler_length) as i32
+    }
+
+    // Get the id of the created status from the api response
+    pub fn post_id(response: &str) -> Result<String, BotError> {
+        parse_id(response)
+    }
+
+    // Publish the status and return its id
+    pub async fn publish(&self) -> Result<String, BotError> {
+        let response = post_multipart(self).await.map_err(from_request_error)?;
+        Self::post_id(&response)
+    }
+
+    // The post with the default hashtags, or if it is too long the words that
+    // fit followed by an ellipsis and the hashtags
+    fn trim_post(post: String, limits: &InstanceLimits) -> String {
+        let max_length = limits.max_characters.max(0) as usize;
+        let reserved_url_length = limits.characters_reserved_per_url.max(0) as usize;
+        let full_post = post.clone() + DEFAULT_HASHTAGS;
+        if mastodon_text::length(&full_post, reserved_url_length) <= max_length {
+            return full_post;
         }
+        let ending = format!("...\n{}", DEFAULT_HASHTAGS);
+        let available_length =
+            max_length.saturating_sub(mastodon_text::length(&ending, reserved_url_length));
+        mastodon_text::truncate(&post, available_length, reserved_url_length) + &ending
     }
 
-    fn trim_post(post: String, max_length: i32, source_url: &str) -> String {
-        let post_body_length = post.chars().count() as i32;
-        let url_length = source_url.chars().count() as i32;
-        let default_hashtags_length = DEFAULT_HASHTAGS.chars().count() as i32;
-        let available_post_size =
-            post_body_length - url_length + default_hashtags_length + MASTODON_URL_LENGTH;
-        if available_post_size <= max_length {
-            let post = post.clone() + DEFAULT_HASHTAGS;
-            return post;
+    // The post with the default hashtags if it fits, or else the statuses of
+    // the thread: everything before the description, then the description cut
+    // at word boundaries, all ending with a "1/3" counter
+    fn split_thread(post: &str, headline: Option<&str>, limits: &InstanceLimits) -> Vec<String> {
+        let max_length = limits.max_characters.max(0) as usize;
+        let reserved_url_length = limits.characters_reserved_per_url.max(0) as usize;
+        let full_post = post.to_string() + DEFAULT_HASHTAGS;
+        if post.trim().is_empty()
+            || mastodon_text::length(&full_post, reserved_url_length) <= max_length
+        {
+            return vec![full_post];
         }
-        let new_post_length = max_length - default_hashtags_length - 5;
-        let post = post
-            .chars()
-            .take(new_post_length as usize)
-            .collect::<String>();
-        post + "...\n" + DEFAULT_HASHTAGS
+        // a template without the description is split like a description
+        let headline = headline
+            .map(str::trim_end)
+            .filter(|headline| !headline.is_empty() && post.starts_with(headline));
+        let (headline, description) = match headline {
+            Some(headline) => (headline, post[headline.len()..].trim()),
+            None => ("", post.trim()),
+        };
+        let hashtags = format!("\n{}", DEFAULT_HASHTAGS);
+        // the counters take as many digits as the number of statuses
+        let mut count = 1;
+        let parts = loop {
+            let counter_length = format!("\n\n{}/{}", count, count).chars().count();
+            let available_length = max_length.saturating_sub(counter_length);
+            let first_available_length = available_length
+                .saturating_sub(mastodon_text::length(&hashtags, reserved_url_length));
+            let mut parts = vec![];
+            if !headline.is_empty() {
+                if mastodon_text::length(headline, reserved_url_length) <= first_available_length {
+                    parts.push(headline.to_string());
+                } else {
+                    let headline = mastodon_text::truncate(
+                        headline,
+                        first_available_length.saturating_sub(3),
+                        reserved_url_length,
+                    );
+                    parts.push(headline + "...");
+                }
+            }
+            let mut rest = description;
+            while !rest.is_empty() {
+                let part_length = match parts.is_empty() {
+                    true => first_available_length,
+                    false => available_length,
+                };
+                let mut part = mastodon_text::truncate(rest, part_length, reserved_url_length);
+                if part.is_empty() {
+                    // a word longer than a whole status
+                    part = rest.chars().take(part_length.max(1)).collect();
+                }
+                rest = rest[part.len()..].trim_start();
+                parts.push(part);
+            }
+            if parts.len().to_string().len() <= count.to_string().len() {
+                break parts;
+            }
+            count = parts.len();
+        };
+        let total = parts.len();
+        parts
+            .into_iter()
+            .enumerate()
+            .map(|(i, part)| {
+                let hashtags = if i == 0 { hashtags.as_str() } else { "" };
+                format!("{}{}\n\n{}/{}", part, hashtags, i + 1, total)
+            })
+            .collect()
     }
 }
 
@@ -76,27 +360,48 @@ impl<'a> RequestData for MastodonPost<'a> {
     }
     fn headers(&self) -> HeaderMap {
         let mut headers = HeaderMap::new();
-        let auth_key = format!("Bearer {}", &self.config.token);
-        headers.insert(
-            reqwest::header::AUTHORIZATION,
-            reqwest::header::HeaderValue::from_str(&auth_key).unwrap(),
-        );
+        // the token is checked by Config::new
+        if let Ok(authorization) = self.config.authorization() {
+            headers.insert(reqwest::header::AUTHORIZATION, authorization);
+        }
         headers.insert(
             reqwest::header::CONTENT_TYPE,
             reqwest::header::HeaderValue::from_static("multipart/form-data"),
         );
+        if let Ok(idempotency_key) = reqwest::header::HeaderValue::from_str(&self.idempotency_key) {
+            headers.insert("Idempotency-Key", idempotency_key);
+        }
         headers
     }
 
-    fn json_multipart(&self) -> reqwest::blocking::multipart::Form {
-        let status = reqwest::blocking::multipart::Part::text(self.post_text.clone());
-        let visibility = reqwest::blocking::multipart::Part::text("public".to_string());
+    // mastodon creates the status only once per Idempotency-Key
+    fn idempotent(&self) -> bool {
+        true
+    }
+
+    fn json_multipart(&self) -> Result<reqwest::multipart::Form, BotError> {
+        let status = reqwest::multipart::Part::text(self.post_text.clone());
+        let visibility =
+            reqwest::multipart::Part::text(self.options.visibility.as_str().to_string());
         let media_ids = self.image_ids.join(",");
-        let media_ids = reqwest::blocking::multipart::Part::text(media_ids);
-        reqwest::blocking::multipart::Form::new()
+        let media_ids = reqwest::multipart::Part::text(media_ids);
+        let mut form = reqwest::multipart::Form::new()
             .part("status", status)
             .part("visibility", visibility)
-            .part("media_ids[]", media_ids)
+            .part("media_ids[]", media_ids);
+        if let Some(in_reply_to_id) = &self.in_reply_to_id {
+            form = form.text("in_reply_to_id", in_reply_to_id.clone());
+        }
+        if let Some(language) = &self.options.language {
+            form = form.text("language", language.clone());
+        }
+        if let Some(spoiler_text) = &self.options.spoiler_text {
+            form = form.text("spoiler_text", spoiler_text.clone());
+        }
+        if self.options.sensitive {
+            form = form.text("sensitive", "true");
+        }
+        Ok(form)
     }
 }
 
@@ -108,13 +413,12 @@ pub struct MastodonImageUploader<'a> {
 
 impl<'a> MastodonImageUploader<'a> {
     // Upload image to mastodon and return image id
-    pub fn upload(&self) -> Result<String, Box<dyn Error>> {
+    pub async fn upload(&self) -> Result<String, BotError> {
         let mut headers = HeaderMap::new();
-        let auth_key = format!("Bearer {}", &self.config.token);
-        headers.insert(
-            reqwest::header::AUTHORIZATION,
-            reqwest::header::HeaderValue::from_str(&auth_key).unwrap(),
-        );
+        // the token is checked by Config::new
+        if let Ok(authorization) = self.config.authorization() {
+            headers.insert(reqwest::header::AUTHORIZATION, authorization);
+        }
 
         let file = FileUpload {
             upload_url: self.config.image_api_url.clone(),
@@ -123,16 +427,123 @@ impl<'a> MastodonImageUploader<'a> {
             description: self.image_title.to_string(),
             params: vec![],
         };
-        let result = upload_file(file)?;
-        let json_result: serde_json::Value = serde_json::from_str(&result)?;
-        let id = match json_result["id"].as_str() {
-            Some(id) => id,
-            None => {
-                error!("Cannot get image id from response: {}", result);
-                std::process::exit(1);
+        let response = upload_file(file).await.map_err(from_request_error)?;
+        parse_id(&response)
+    }
+}
+
+// Publishes the posts to the mastodon account from the config
+pub struct MastodonPublisher<'a> {
+    config: &'a MastodonConfig,
+    formatter: Box<dyn Formatter + 'a>,
+}
+
+impl<'a> MastodonPublisher<'a> {
+    pub fn new(config: &'a MastodonConfig) -> Self {
+        let template = PostTemplate::or_default(
+            "mastodon",
+            config.template.as_deref(),
+            &config.translations,
+            config.tz,
+        );
+        Self::with_formatter(config, Box::new(template))
+    }
+
+    pub fn with_formatter(config: &'a MastodonConfig, formatter: Box<dyn Formatter + 'a>) -> Self {
+        Self { config, formatter }
+    }
+
+    // One status, or a thread in thread mode
+    fn posts(
+        &self,
+        data: &apis::SeasonData,
+        has_image: bool,
+        image_id: Option<String>,
+    ) -> Vec<MastodonPost<'a>> {
+        let post = self.formatter.format(data, has_image);
+        if self.config.thread {
+            let headline = self.formatter.headline(data, has_image);
+            MastodonPost::thread(post, headline.as_deref(), data, self.config, image_id)
+        } else {
+            vec![MastodonPost::new(post, data, self.config, image_id)]
+        }
+    }
+
+    // The post is published without the image if the upload fails
+    // or if the instance does not accept the image
+    async fn upload_image(&self, data: &apis::SeasonData, image_path: &str) -> Option<String> {
+        let limits = self.config.limits();
+        if limits.max_media_attachments == Some(0) {
+            return None;
+        }
+        if let Some(image_size_limit) = limits.image_size_limit {
+            let image_size = std::fs::metadata(image_path)
+                .map(|metadata| metadata.len())
+                .unwrap_or_default();
+            if image_size > image_size_limit {
+                warn!(
+                    "Image {} is too large for the instance: {} bytes, at most {}",
+                    image_path, image_size, image_size_limit
+                );
+                return None;
+            }
+        }
+        let image_uploader = MastodonImageUploader {
+            config: self.config,
+            image_path,
+            image_title: &data.title,
+        };
+        match image_uploader.upload().await {
+            Ok(id) => Some(id),
+            Err(err) => {
+                error!("Cannot upload image {}: {}", image_path, err);
+                None
             }
+        }
+    }
+}
+
+#[async_trait]
+impl<'a> Publisher for MastodonPublisher<'a> {
+    fn name(&self) -> &str {
+        "mastodon"
+    }
+
+    async fn publish(
+        &self,
+        data: &apis::SeasonData,
+        image_path: Option<String>,
+    ) -> Result<String, BotError> {
+        let image_id = match image_path {
+            Some(image_path) => self.upload_image(data, &image_path).await,
+            None => None,
         };
-        Ok(id.to_string())
+        let mut posts = self.posts(data, image_id.is_some(), image_id).into_iter();
+        let post = posts.next().expect("a thread has a first status");
+        let status_id = post.publish().await?;
+        // the headline is published, a failed reply only ends the thread early
+        let mut previous_id = status_id.clone();
+        for mut reply in posts {
+            reply.in_reply_to_id = Some(previous_id.clone());
+            match reply.publish().await {
+                Ok(reply_id) => previous_id = reply_id,
+                Err(err) => {
+                    error!("Cannot post the rest of the thread: {}", err);
+                    break;
+                }
+            }
+        }
+        Ok(status_id)
+    }
+
+    fn preview(&self, data: &apis::SeasonData, image_path: Option<String>) -> PostPreview {
+        let mut posts = self.posts(data, image_path.is_some(), None).into_iter();
+        let post = posts.next().expect("a thread has a first status");
+        let mut preview = PostPreview::from_mastodon_post(&post, image_path);
+        preview.replies = posts
+            .map(|reply| PostPreview::from_mastodon_post(&reply, None))
+            .collect();
+        preview
     }
 }
 
@@ -141,9 +552,21 @@ mod tests {
 
     use super::*;
 
+    // The post the publisher sends, with the text of its formatter
+    fn formatted_post<'a>(
+        data: &apis::SeasonData,
+        config: &'a MastodonConfig,
+        image_id: Option<String>,
+    ) -> MastodonPost<'a> {
+        let publisher = MastodonPublisher::new(config);
+        let post = publisher.formatter.format(data, image_id.is_some());
+        MastodonPost::new(post, data, config, image_id)
+    }
+
     #[test]
-    fn test_from_season_data_all_data() {
+    fn test_formatted_post_all_data() {
         let test_season_data = apis::SeasonData {
+            show_id: 1,
             title: String::from("title"),
             url: String::from("url"),
             language: Some(String::from("en")),
@@ -152,6 +575,8 @@ mod tests {
             image_url: Some(String::from("image_url")),
             season_number: 1,
             host: Some(String::from("host")),
+            airstamp: None,
+            airtime: None,
         };
         let test_config_string = String::from(
             r#"
@@ -164,7 +589,7 @@ mod tests {
         );
         let config = MastodonConfig::new(&test_config_string).unwrap();
         let image_id = Some(String::from("image_id"));
-        let masto_post = MastodonPost::from_season_data(&test_season_data, &config, image_id);
+        let masto_post = formatted_post(&test_season_data, &config, image_id);
         let when = chrono::Utc::now().format("%d %B %Y").to_string();
 
         let test_post_text = format!(
@@ -183,8 +608,9 @@ mod tests {
         assert_eq!(test_post_text, masto_post.post_text);
     }
     #[test]
-    fn test_from_season_data_missing_fields() {
+    fn test_formatted_post_missing_fields() {
         let test_season_data = apis::SeasonData {
+            show_id: 1,
             title: String::from("title"),
             url: String::from("url"),
             language: None,
@@ -193,6 +619,8 @@ mod tests {
             image_url: None,
             season_number: 1,
             host: None,
+            airstamp: None,
+            airtime: None,
         };
         let test_config_string = String::from(
             r#"
@@ -205,7 +633,7 @@ mod tests {
         );
         let config = MastodonConfig::new(&test_config_string).unwrap();
         let image_id = None;
-        let masto_post = MastodonPost::from_season_data(&test_season_data, &config, image_id);
+        let masto_post = formatted_post(&test_season_data, &config, image_id);
         let when = chrono::Utc::now().format("%d %B %Y").to_string();
 
         let test_post_text = format!(
@@ -227,6 +655,7 @@ mod tests {
     #[test]
     fn test_url() {
         let test_season_data = apis::SeasonData {
+            show_id: 1,
             title: String::from("title"),
             url: String::from("url"),
             language: None,
@@ -235,6 +664,8 @@ mod tests {
             image_url: None,
             season_number: 1,
             host: None,
+            airstamp: None,
+            airtime: None,
         };
         let test_config_string = String::from(
             r#"
@@ -247,7 +678,7 @@ mod tests {
         );
         let config = MastodonConfig::new(&test_config_string).unwrap();
         let image_id = None;
-        let masto_post = MastodonPost::from_season_data(&test_season_data, &config, image_id);
+        let masto_post = formatted_post(&test_season_data, &config, image_id);
         let test_url = String::from("https://your.mastodon.instance/api/v1/statuses");
         assert_eq!(test_url, masto_post.url());
     }
@@ -255,6 +686,7 @@ mod tests {
     #[test]
     fn test_headers() {
         let test_season_data = apis::SeasonData {
+            show_id: 1,
             title: String::from("title"),
             url: String::from("url"),
             language: None,
@@ -263,6 +695,8 @@ mod tests {
             image_url: None,
             season_number: 1,
             host: None,
+            airstamp: None,
+            airtime: None,
         };
         let test_config_string = String::from(
             r#"
@@ -274,7 +708,7 @@ mod tests {
         );
         let config = MastodonConfig::new(&test_config_string).unwrap();
         let image_id = None;
-        let masto_post = MastodonPost::from_season_data(&test_season_data, &config, image_id);
+        let masto_post = formatted_post(&test_season_data, &config, image_id);
         let mut test_headers = HeaderMap::new();
         test_headers.insert(
             reqwest::header::AUTHORIZATION,
@@ -284,32 +718,331 @@ mod tests {
             reqwest::header::CONTENT_TYPE,
             reqwest::header::HeaderValue::from_static("multipart/form-data"),
         );
+        let idempotency_key = format!(
+            "tv_series_bot-1-1-{}",
+            utils::today(&chrono_tz::UTC).format("%Y-%m-%d")
+        );
+        test_headers.insert(
+            "Idempotency-Key",
+            reqwest::header::HeaderValue::from_str(&idempotency_key).unwrap(),
+        );
         assert_eq!(test_headers, masto_post.headers());
     }
 
     #[test]
-    fn test_hastag_string_na() {
-        let test_string = None;
-        let result = utils::hashtag_string_or_na(&test_string);
-        assert_eq!(result, "N/A");
+    fn test_text_length() {
+        let test_season_data = apis::SeasonData {
+            show_id: 1,
+            title: String::from("title"),
+            url: String::from("https://www.tvmaze.com/shows/1/a-very-long-show-name-for-the-test"),
+            language: None,
+            description: None,
+            genres: vec![],
+            image_url: None,
+            season_number: 1,
+            host: None,
+            airstamp: None,
+            airtime: None,
+        };
+        let test_config_string = String::from(
+            r#"
+            token = "mastodon token"
+            url = "https://your.mastodon.instance"
+            image_api_url = "https://your.mastodon.instance/api/v2/media"
+            max_post_len = 500
+        "#,
+        );
+        let config = MastodonConfig::new(&test_config_string).unwrap();
+        let masto_post = formatted_post(&test_season_data, &config, None);
+        let raw_length = masto_post.post_text.chars().count() as i32;
+        let url_length = test_season_data.url.chars().count() as i32;
+        assert_eq!(
+            masto_post.text_length(),
+            raw_length - url_length + crate::mastodon_instance::DEFAULT_URL_LENGTH
+        );
     }
 
     #[test]
-    fn test_hastag_string_sign() {
-        let test_string = Some("disnay +".to_string());
-        let result = utils::hashtag_string_or_na(&test_string);
-        assert_eq!(result, "#disnay");
+    fn test_instance_limits() {
+        let test_season_data = apis::SeasonData {
+            show_id: 1,
+            title: String::from("title"),
+            url: String::from("https://www.tvmaze.com/shows/1/a-very-long-show-name-for-the-test"),
+            language: None,
+            description: Some("word ".repeat(100)),
+            genres: vec![],
+            image_url: None,
+            season_number: 1,
+            host: None,
+            airstamp: None,
+            airtime: None,
+        };
+        let test_config_string = String::from(
+            r#"
+            token = "mastodon token"
+            url = "https://your.mastodon.instance"
+            image_api_url = "https://your.mastodon.instance/api/v2/media"
+        "#,
+        );
+        let config = MastodonConfig::new(&test_config_string).unwrap();
+        assert_eq!(config.limits().max_characters, 500);
+        let masto_post = formatted_post(&test_season_data, &config, None);
+        assert!(masto_post.text_length() > 300);
+        config.set_instance_limits(InstanceLimits {
+            max_characters: 300,
+            characters_reserved_per_url: 23,
+            image_size_limit: None,
+            max_media_attachments: Some(4),
+        });
+        let masto_post = formatted_post(&test_season_data, &config, None);
+        assert!(masto_post.text_length() <= 300);
+        assert!(masto_post.post_text.ends_with("...\n#tvseries #tvshows"));
+    }
+
+    #[test]
+    fn test_trim_post() {
+        let limits = InstanceLimits {
+            max_characters: 75,
+            characters_reserved_per_url: 23,
+            image_size_limit: None,
+            max_media_attachments: None,
+        };
+        let post = String::from(
+            "Space Show\nhttps://www.tvmaze.com/shows/1/a-very-long-show-name-for-the-test\n",
+        );
+        // the url counts as 23 chars, so the post fits
+        assert_eq!(
+            MastodonPost::trim_post(post.clone(), &limits),
+            post + DEFAULT_HASHTAGS
+        );
+        let post = String::from(
+            "Space Show returns https://www.tvmaze.com/shows/1/space-show with #SciFi and #Drama\n",
+        );
+        // one char short for #SciFi, which is not cut in half
+        let trimmed = MastodonPost::trim_post(post, &limits);
+        assert_eq!(
+            trimmed,
+            "Space Show returns https://www.tvmaze.com/shows/1/space-show with...\n#tvseries #tvshows"
+        );
+        assert!(mastodon_text::length(&trimmed, 23) <= 75);
     }
+
     #[test]
-    fn test_hastag_string_digits() {
-        let test_string = Some("chanal 4".to_string());
-        let result = utils::hashtag_string_or_na(&test_string);
-        assert_eq!(result, "#chanal4");
+    fn test_split_thread() {
+        let limits = InstanceLimits {
+            max_characters: 100,
+            characters_reserved_per_url: 23,
+            image_size_limit: None,
+            max_media_attachments: None,
+        };
+        let description = "First sentence of the synopsis. ".repeat(5);
+        let post = format!(
+            "Space Show\nhttps://www.tvmaze.com/shows/1/space-show\nSeason: 2\n\n{}\n",
+            description
+        );
+        let headline = "Space Show\nhttps://www.tvmaze.com/shows/1/space-show\nSeason: 2\n\n";
+        let statuses = MastodonPost::split_thread(&post, Some(headline), &limits);
+        assert_eq!(statuses.len(), 3);
+        assert_eq!(
+            statuses[0],
+            "Space Show\nhttps://www.tvmaze.com/shows/1/space-show\nSeason: 2\n#tvseries #tvshows\n\n1/3"
+        );
+        assert!(statuses[1].starts_with("First sentence of the synopsis."));
+        assert!(statuses[1].ends_with("\n\n2/3"));
+        assert!(statuses[2].ends_with("synopsis.\n\n3/3"));
+        for status in statuses.iter() {
+            assert!(mastodon_text::length(status, 23) <= 100);
+        }
+        let words: Vec<&str> = statuses[1..]
+            .iter()
+            .flat_map(|status| status.rsplit_once("\n\n").unwrap().0.split_whitespace())
+            .collect();
+        assert_eq!(words.join(" "), description.trim());
+        // short posts are not threaded
+        let statuses = MastodonPost::split_thread("Space Show\n", Some(""), &limits);
+        assert_eq!(statuses, vec!["Space Show\n#tvseries #tvshows"]);
     }
+
     #[test]
-    fn test_hastag_string_dash() {
-        let test_string = Some("Science-Fiction".to_string());
-        let result = utils::hashtag_string_or_na(&test_string);
-        assert_eq!(result, "#ScienceFiction");
+    fn test_thread_replies_are_unlisted() {
+        let test_config_string = String::from(
+            r#"
+            token = "mastodon token"
+            url = "https://your.mastodon.instance"
+            image_api_url = "https://your.mastodon.instance/api/v2/media"
+            max_post_len = 100
+            thread = true
+            sensitive = true
+        "#,
+        );
+        let config = MastodonConfig::new(&test_config_string).unwrap();
+        let description = "word ".repeat(40);
+        let data = apis::SeasonData {
+            show_id: 1,
+            title: String::from("Space Show"),
+            url: String::from("url"),
+            language: Some(String::from("English")),
+            description: Some(description.clone()),
+            genres: vec![],
+            image_url: None,
+            season_number: 1,
+            host: None,
+            airstamp: None,
+            airtime: None,
+        };
+        let post = format!("Space Show\n\n{}", description);
+        let posts = MastodonPost::thread(
+            post,
+            Some("Space Show\n\n"),
+            &data,
+            &config,
+            Some(String::from("image id")),
+        );
+        assert_eq!(posts.len(), 4);
+        assert_eq!(posts[0].options.visibility, Visibility::Public);
+        assert!(posts[0].options.sensitive);
+        assert_eq!(posts[0].image_ids, vec![String::from("image id")]);
+        assert!(posts[1..].iter().all(|post| {
+            post.options.visibility == Visibility::Unlisted
+                && post.options.language.as_deref() == Some("en")
+                && post.image_ids.is_empty()
+        }));
+        let run_date = utils::today(&config.tz).format("%Y-%m-%d").to_string();
+        assert_eq!(
+            posts[0].idempotency_key,
+            format!("tv_series_bot-1-1-{}", run_date)
+        );
+        assert_eq!(
+            posts[3].idempotency_key,
+            format!("tv_series_bot-1-1-{}-4", run_date)
+        );
+    }
+
+    #[test]
+    fn test_thread_with_filtered_description() {
+        let test_config_string = String::from(
+            r#"
+            token = "mastodon token"
+            url = "https://your.mastodon.instance"
+            image_api_url = "https://your.mastodon.instance/api/v2/media"
+            max_post_len = 100
+            thread = true
+            template = "{{ title }}\n{{ url }}\n\n{{ description | upper }}\n"
+        "#,
+        );
+        let config = MastodonConfig::new(&test_config_string).unwrap();
+        let data = apis::SeasonData {
+            show_id: 1,
+            title: String::from("Space Show"),
+            url: String::from("https://www.tvmaze.com/shows/1/space-show"),
+            language: None,
+            description: Some("word ".repeat(40)),
+            genres: vec![],
+            image_url: None,
+            season_number: 1,
+            host: None,
+            airstamp: None,
+            airtime: None,
+        };
+        let publisher = MastodonPublisher::new(&config);
+        let posts = publisher.posts(&data, false, None);
+        assert!(posts.len() > 2);
+        assert!(posts[0]
+            .post_text
+            .starts_with("Space Show\nhttps://www.tvmaze.com/shows/1/space-show\n#tvseries"));
+        assert!(posts[1].post_text.starts_with("WORD WORD"));
+    }
+
+    #[test]
+    fn test_status_options() {
+        let test_config_string = String::from(
+            r#"
+            token = "mastodon token"
+            url = "https://your.mastodon.instance"
+            image_api_url = "https://your.mastodon.instance/api/v2/media"
+            max_post_len = 100
+            visibility = "unlisted"
+
+            [[content_warnings]]
+            genres = ["Horror", "Thriller"]
+            spoiler_text = "Horror"
+            sensitive = true
+
+            [[content_warnings]]
+            keywords = ["murder"]
+            spoiler_text = "Violence"
+        "#,
+        );
+        let config = MastodonConfig::new(&test_config_string).unwrap();
+        let mut data = apis::SeasonData {
+            show_id: 1,
+            title: String::from("Space Show"),
+            url: String::from("url"),
+            language: Some(String::from("Klingon")),
+            description: Some(String::from("Ships fly to space.")),
+            genres: vec![String::from("Science-Fiction")],
+            image_url: None,
+            season_number: 1,
+            host: None,
+            airstamp: None,
+            airtime: None,
+        };
+        let options = StatusOptions::for_season(&config, &data);
+        assert_eq!(
+            options,
+            StatusOptions {
+                visibility: Visibility::Unlisted,
+                ..StatusOptions::default()
+            }
+        );
+        data.genres.push(String::from("horror"));
+        data.description = Some(String::from("A Murder on the ship."));
+        let options = StatusOptions::for_season(&config, &data);
+        assert_eq!(options.spoiler_text.as_deref(), Some("Horror, Violence"));
+        assert!(options.sensitive);
+        // the content warning takes part of the length limit
+        let post = MastodonPost::new("word ".repeat(30), &data, &config, None);
+        assert!(post.text_length() <= 100);
+        assert!(mastodon_text::length(&post.post_text, 23) <= 100 - "Horror, Violence".len());
+    }
+
+    #[test]
+    fn test_post_id() {
+        let response = r#"{"id": "103704874086360371", "content": "<p>title</p>"}"#;
+        assert_eq!(
+            MastodonPost::post_id(response).unwrap(),
+            String::from("103704874086360371")
+        );
+        let err = MastodonPost::post_id(r#"{"error": "Validation failed"}"#).unwrap_err();
+        assert_eq!(err.to_string(), "mastodon: Validation failed");
+        let err = MastodonPost::post_id("<html>Bad Gateway</html>").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "mastodon: unexpected response: <html>Bad Gateway</html>"
+        );
+    }
+
+    #[test]
+    fn test_error_from_status() {
+        let err = from_request_error(BotError::HttpStatus {
+            endpoint: String::from("statuses"),
+            status: 422,
+            body: String::from(
+                r#"{"error": "Validation failed: Text character limit of 500 exceeded"}"#,
+            ),
+        });
+        assert_eq!(
+            err.to_string(),
+            "mastodon HTTP 422: Validation failed: Text character limit of 500 exceeded"
+        );
+        let err = mastodon_error(
+            Some(401),
+            r#"{"error": "invalid_token", "error_description": "The access token is invalid"}"#,
+        );
+        assert!(matches!(
+            err,
+            BotError::Platform { status: Some(401), message, .. }
+                if message == "invalid_token: The access token is invalid"
+        ));
     }
 }
@@ -0,0 +1,251 @@
+use crate::config::MastodonConfig;
+use crate::error::BotError;
+use crate::requests::{self, RequestData};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+
+// Length counted by mastodon for every url, whatever its real length
+pub const DEFAULT_URL_LENGTH: i32 = 23;
+
+// Limits of the mastodon instance, detected from the instance api or taken from the config
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InstanceLimits {
+    pub max_characters: i32,
+    pub characters_reserved_per_url: i32,
+    // bytes, None when the instance does not tell
+    pub image_size_limit: Option<u64>,
+    pub max_media_attachments: Option<usize>,
+}
+
+impl InstanceLimits {
+    // Fallback when the instance api is unavailable
+    pub fn from_config(config: &MastodonConfig) -> Self {
+        Self {
+            max_characters: config.max_post_len,
+            characters_reserved_per_url: DEFAULT_URL_LENGTH,
+            image_size_limit: None,
+            max_media_attachments: None,
+        }
+    }
+}
+
+// /api/v2/instance, /api/v1/instance of older servers and Akkoma's max_toot_chars
+#[derive(Deserialize, Debug)]
+struct InstanceResponse {
+    configuration: Option<InstanceConfiguration>,
+    max_toot_chars: Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct InstanceConfiguration {
+    statuses: Option<StatusesConfiguration>,
+    media_attachments: Option<MediaConfiguration>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StatusesConfiguration {
+    max_characters: Option<i32>,
+    max_media_attachments: Option<usize>,
+    characters_reserved_per_url: Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MediaConfiguration {
+    image_size_limit: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CachedLimits {
+    url: String,
+    fetched_at: DateTime<Utc>,
+    limits: InstanceLimits,
+}
+
+struct InstanceRequest {
+    url: String,
+}
+
+impl RequestData for InstanceRequest {
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+}
+
+// The limits from the instance response, the fallback ones for what it does not tell
+fn parse_limits(body: &str, fallback: &InstanceLimits) -> Result<InstanceLimits, BotError> {
+    let response: InstanceResponse = serde_json::from_str(body)
+        .map_err(|err| BotError::Parse(format!("mastodon instance: {}", err)))?;
+    let configuration = response.configuration;
+    let statuses = configuration
+        .as_ref()
+        .and_then(|configuration| configuration.statuses.as_ref());
+    let media = configuration
+        .as_ref()
+        .and_then(|configuration| configuration.media_attachments.as_ref());
+    let max_characters = statuses
+        .and_then(|statuses| statuses.max_characters)
+        .or(response.max_toot_chars);
+    let Some(max_characters) = max_characters else {
+        return Err(BotError::Parse(String::from(
+            "mastodon instance: no max_characters",
+        )));
+    };
+    Ok(InstanceLimits {
+        max_characters,
+        characters_reserved_per_url: statuses
+            .and_then(|statuses| statuses.characters_reserved_per_url)
+            .unwrap_or(fallback.characters_reserved_per_url),
+        image_size_limit: media
+            .and_then(|media| media.image_size_limit)
+            .or(fallback.image_size_limit),
+        max_media_attachments: statuses
+            .and_then(|statuses| statuses.max_media_attachments)
+            .or(fallback.max_media_attachments),
+    })
+}
+
+async fn fetch_limits(config: &MastodonConfig) -> Result<InstanceLimits, BotError> {
+    let fallback = InstanceLimits::from_config(config);
+    let mut last_error = None;
+    for path in ["/api/v2/instance", "/api/v1/instance"] {
+        let request = InstanceRequest {
+            url: config.url.clone() + path,
+        };
+        match requests::get(&request).await {
+            Ok(body) => match parse_limits(&body, &fallback) {
+                Ok(limits) => return Ok(limits),
+                Err(err) => last_error = Some(err),
+            },
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| BotError::Parse(String::from("mastodon instance"))))
+}
+
+fn read_cache(cache_file: Option<&str>, url: &str) -> Option<CachedLimits> {
+    let content = fs::read_to_string(cache_file?).ok()?;
+    let cached: CachedLimits = serde_json::from_str(&content).ok()?;
+    (cached.url == url).then_some(cached)
+}
+
+fn write_cache(cache_file: &str, cached: &CachedLimits) -> Result<(), BotError> {
+    let content = serde_json::to_string_pretty(cached)
+        .map_err(|err| BotError::Parse(format!("mastodon instance cache: {}", err)))?;
+    fs::write(cache_file, content).map_err(|err| BotError::io(cache_file, err))
+}
+
+fn is_fresh(cached: &CachedLimits, ttl_seconds: u64, now: DateTime<Utc>) -> bool {
+    let age = now - cached.fetched_at;
+    age >= chrono::Duration::zero() && age < chrono::Duration::seconds(ttl_seconds as i64)
+}
+
+// Limits of the instance: from the cache file while it is younger than
+// `instance_cache_ttl`, then from the instance api, and if that fails from
+// the outdated cache or at last from the config. Without a cache file the
+// instance api is asked every time
+pub async fn detect_limits(config: &MastodonConfig, cache_file: Option<&str>) -> InstanceLimits {
+    let cached = read_cache(cache_file, &config.url);
+    if let Some(cached) = &cached {
+        if is_fresh(cached, config.instance_cache_ttl, Utc::now()) {
+            return cached.limits.clone();
+        }
+    }
+    match fetch_limits(config).await {
+        Ok(limits) => {
+            info!(
+                "Mastodon instance allows {} characters per post, urls count as {}",
+                limits.max_characters, limits.characters_reserved_per_url
+            );
+            let cached = CachedLimits {
+                url: config.url.clone(),
+                fetched_at: Utc::now(),
+                limits,
+            };
+            if let Some(cache_file) = cache_file {
+                if let Err(err) = write_cache(cache_file, &cached) {
+                    warn!("Cannot cache the mastodon instance limits: {}", err);
+                }
+            }
+            cached.limits
+        }
+        Err(err) => {
+            warn!("Cannot get the mastodon instance limits: {}", err);
+            match cached {
+                Some(cached) => cached.limits,
+                None => InstanceLimits::from_config(config),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn fallback() -> InstanceLimits {
+        InstanceLimits {
+            max_characters: 500,
+            characters_reserved_per_url: DEFAULT_URL_LENGTH,
+            image_size_limit: None,
+            max_media_attachments: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_v2_instance() {
+        let body = r#"{
+            "domain": "glitch.example",
+            "configuration": {
+                "statuses": {"max_characters": 5000, "max_media_attachments": 4, "characters_reserved_per_url": 23},
+                "media_attachments": {"image_size_limit": 16777216, "video_size_limit": 103809024}
+            }
+        }"#;
+        let limits = parse_limits(body, &fallback()).unwrap();
+        assert_eq!(limits.max_characters, 5000);
+        assert_eq!(limits.max_media_attachments, Some(4));
+        assert_eq!(limits.image_size_limit, Some(16777216));
+    }
+
+    #[test]
+    fn test_parse_akkoma_instance() {
+        let body = r#"{"uri": "akkoma.example", "max_toot_chars": 8000}"#;
+        let limits = parse_limits(body, &fallback()).unwrap();
+        assert_eq!(limits.max_characters, 8000);
+        assert_eq!(limits.characters_reserved_per_url, DEFAULT_URL_LENGTH);
+        assert!(parse_limits(r#"{"uri": "old.example"}"#, &fallback()).is_err());
+        assert!(parse_limits("<html></html>", &fallback()).is_err());
+    }
+
+    #[test]
+    fn test_cache() {
+        let cache_file = std::env::temp_dir().join(format!(
+            "tv_series_bot_instance_{}.json",
+            std::process::id()
+        ));
+        let cache_file = cache_file.to_str().unwrap();
+        let fetched_at = Utc.with_ymd_and_hms(2026, 10, 1, 12, 0, 0).unwrap();
+        let cached = CachedLimits {
+            url: String::from("https://gts.example"),
+            fetched_at,
+            limits: fallback(),
+        };
+        write_cache(cache_file, &cached).unwrap();
+        let cached = read_cache(Some(cache_file), "https://gts.example").unwrap();
+        assert_eq!(cached.limits, fallback());
+        assert!(read_cache(Some(cache_file), "https://other.example").is_none());
+        assert!(is_fresh(
+            &cached,
+            3600,
+            fetched_at + chrono::Duration::minutes(30)
+        ));
+        assert!(!is_fresh(
+            &cached,
+            3600,
+            fetched_at + chrono::Duration::hours(2)
+        ));
+        fs::remove_file(cache_file).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+// Post length as mastodon counts it: every url takes the reserved url length
+// and a remote mention `@user@domain` only counts as `@user`
+
+// Chars mastodon does not include at the end of a url or a mention
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ':', ';', '!', '?', ')', '"', '\''];
+
+// Length of one word (no whitespace in it)
+fn word_length(word: &str, reserved_url_length: usize) -> usize {
+    let start = word.find("https://").or_else(|| word.find("http://"));
+    if let Some(start) = start {
+        let (prefix, link) = word.split_at(start);
+        let url = link.trim_end_matches(TRAILING_PUNCTUATION);
+        let punctuation = &link[url.len()..];
+        return prefix.chars().count() + reserved_url_length + punctuation.chars().count();
+    }
+    if let Some(mention) = word.strip_prefix('@') {
+        let mention = mention.trim_end_matches(TRAILING_PUNCTUATION);
+        let punctuation = word.len() - 1 - mention.len();
+        if let Some((user, domain)) = mention.split_once('@') {
+            if !user.is_empty() && domain.contains('.') {
+                return 1 + user.chars().count() + punctuation;
+            }
+        }
+    }
+    word.chars().count()
+}
+
+// Words and the whitespace between them, in order
+fn tokens(text: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut start = 0;
+    let mut in_whitespace = None;
+    for (i, c) in text.char_indices() {
+        let is_whitespace = c.is_whitespace();
+        if in_whitespace.is_some_and(|in_whitespace| in_whitespace != is_whitespace) {
+            tokens.push(&text[start..i]);
+            start = i;
+        }
+        in_whitespace = Some(is_whitespace);
+    }
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+    tokens
+}
+
+fn token_length(token: &str, reserved_url_length: usize) -> usize {
+    if token.starts_with(char::is_whitespace) {
+        token.chars().count()
+    } else {
+        word_length(token, reserved_url_length)
+    }
+}
+
+pub fn length(text: &str, reserved_url_length: usize) -> usize {
+    tokens(text)
+        .iter()
+        .map(|token| token_length(token, reserved_url_length))
+        .sum()
+}
+
+// The longest start of the text that fits into max_length, cut between two
+// words so that links, hashtags and mentions are never cut in half
+pub fn truncate(text: &str, max_length: usize, reserved_url_length: usize) -> String {
+    let mut end = 0;
+    let mut kept_length = 0;
+    let mut position = 0;
+    for token in tokens(text) {
+        let token_length = token_length(token, reserved_url_length);
+        if kept_length + token_length > max_length {
+            break;
+        }
+        kept_length += token_length;
+        position += token.len();
+        if !token.starts_with(char::is_whitespace) {
+            end = position;
+        }
+    }
+    text[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length() {
+        assert_eq!(length("Space Show", 23), 10);
+        assert_eq!(
+            length("https://www.tvmaze.com/shows/1/a-very-long-name", 23),
+            23
+        );
+        assert_eq!(length("(see https://example.com/a).", 23), 5 + 23 + 2);
+        assert_eq!(length("by @user@mastodon.social!", 23), 3 + 5 + 1);
+        assert_eq!(length("mail@example.com @local", 23), 16 + 1 + 6);
+        assert_eq!(length("Ünïcödé\n\n#hashtag", 23), 17);
+    }
+
+    #[test]
+    fn test_truncate_at_word_boundary() {
+        let text = "One two three https://example.com/a-long-link #Hashtag";
+        assert_eq!(truncate(text, 9, 23), "One two");
+        assert_eq!(truncate(text, 13, 23), "One two three");
+        // the link counts as 23 chars, so it fits even though it is longer
+        assert_eq!(
+            truncate(text, 37, 23),
+            "One two three https://example.com/a-long-link"
+        );
+        assert_eq!(
+            truncate(text, 45, 23),
+            "One two three https://example.com/a-long-link"
+        );
+        assert_eq!(truncate(text, 100, 23), text);
+        assert_eq!(truncate("Supercalifragilistic", 5, 23), "");
+    }
+
+    #[test]
+    fn test_truncate_keeps_lines() {
+        let text = "Title\nhttps://example.com/show\n\nFirst sentence. Second sentence.";
+        assert_eq!(
+            truncate(text, 50, 23),
+            "Title\nhttps://example.com/show\n\nFirst sentence."
+        );
+    }
+}
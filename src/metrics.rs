@@ -0,0 +1,346 @@
+use crate::error::BotError;
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+// Metrics of the process, rendered in the Prometheus text format
+static METRICS: OnceLock<Mutex<Metrics>> = OnceLock::new();
+
+// Upper bounds of the latency histograms, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Counter(f64),
+    Gauge(f64),
+    Histogram {
+        // observations per bucket of LATENCY_BUCKETS, not cumulative
+        buckets: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+impl Value {
+    fn kind(&self) -> &str {
+        match self {
+            Value::Counter(_) => "counter",
+            Value::Gauge(_) => "gauge",
+            Value::Histogram { .. } => "histogram",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Family {
+    help: String,
+    series: BTreeMap<Labels, Value>,
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    families: BTreeMap<String, Family>,
+}
+
+impl Metrics {
+    fn series(
+        &mut self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        empty: Value,
+    ) -> &mut Value {
+        let family = self.families.entry(name.to_string()).or_default();
+        family.help = help.to_string();
+        let labels = labels
+            .iter()
+            .map(|(label, value)| (label.to_string(), value.to_string()))
+            .collect();
+        family.series.entry(labels).or_insert(empty)
+    }
+
+    pub fn add(&mut self, name: &str, help: &str, labels: &[(&str, &str)], value: f64) {
+        if let Value::Counter(total) = self.series(name, help, labels, Value::Counter(0.0)) {
+            *total += value;
+        }
+    }
+
+    pub fn set(&mut self, name: &str, help: &str, labels: &[(&str, &str)], value: f64) {
+        if let Value::Gauge(current) = self.series(name, help, labels, Value::Gauge(0.0)) {
+            *current = value;
+        }
+    }
+
+    pub fn observe(&mut self, name: &str, help: &str, labels: &[(&str, &str)], value: f64) {
+        let empty = Value::Histogram {
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        };
+        if let Value::Histogram {
+            buckets,
+            sum,
+            count,
+        } = self.series(name, help, labels, empty)
+        {
+            if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| value <= *bound) {
+                buckets[bucket] += 1;
+            }
+            *sum += value;
+            *count += 1;
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        for (name, family) in self.families.iter() {
+            let Some(first) = family.series.values().next() else {
+                continue;
+            };
+            let _ = writeln!(text, "# HELP {} {}", name, family.help);
+            let _ = writeln!(text, "# TYPE {} {}", name, first.kind());
+            for (labels, value) in family.series.iter() {
+                match value {
+                    Value::Counter(value) | Value::Gauge(value) => {
+                        let _ = writeln!(text, "{}{} {}", name, format_labels(labels, None), value);
+                    }
+                    Value::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => {
+                        let mut cumulative = 0;
+                        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(buckets.iter()) {
+                            cumulative += bucket;
+                            let le = bound.to_string();
+                            let _ = writeln!(
+                                text,
+                                "{}_bucket{} {}",
+                                name,
+                                format_labels(labels, Some(&le)),
+                                cumulative
+                            );
+                        }
+                        let _ = writeln!(
+                            text,
+                            "{}_bucket{} {}",
+                            name,
+                            format_labels(labels, Some("+Inf")),
+                            count
+                        );
+                        let _ =
+                            writeln!(text, "{}_sum{} {}", name, format_labels(labels, None), sum);
+                        let _ = writeln!(
+                            text,
+                            "{}_count{} {}",
+                            name,
+                            format_labels(labels, None),
+                            count
+                        );
+                    }
+                }
+            }
+        }
+        text
+    }
+}
+
+// {channel="mastodon",result="success"}, empty without labels
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(label, value)| format!("{}=\"{}\"", label, escape_label(value)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    if pairs.is_empty() {
+        return String::new();
+    }
+    format!("{{{}}}", pairs.join(","))
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn metrics() -> MutexGuard<'static, Metrics> {
+    METRICS
+        .get_or_init(|| Mutex::new(Metrics::default()))
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub fn record_fetch(source: &str, duration: Duration) {
+    metrics().observe(
+        "tv_series_bot_fetch_duration_seconds",
+        "Time to fetch the schedule of a day from a source.",
+        &[("source", source)],
+        duration.as_secs_f64(),
+    );
+}
+
+pub fn record_season(genre: &str) {
+    metrics().add(
+        "tv_series_bot_seasons_matched_total",
+        "New seasons matching the target genres, by genre.",
+        &[("genre", genre)],
+        1.0,
+    );
+}
+
+pub fn record_post(channel: &str, success: bool) {
+    let result = if success { "success" } else { "failure" };
+    metrics().add(
+        "tv_series_bot_posts_total",
+        "Published and failed posts, by channel.",
+        &[("channel", channel), ("result", result)],
+        1.0,
+    );
+}
+
+pub fn record_image_download(bytes: usize) {
+    let mut metrics = metrics();
+    metrics.add(
+        "tv_series_bot_image_downloads_total",
+        "Downloaded season images.",
+        &[],
+        1.0,
+    );
+    metrics.add(
+        "tv_series_bot_image_download_bytes_total",
+        "Bytes of the downloaded season images.",
+        &[],
+        bytes as f64,
+    );
+}
+
+pub fn record_retry(host: &str) {
+    metrics().add(
+        "tv_series_bot_http_retries_total",
+        "Http requests that were sent again, by host.",
+        &[("host", host)],
+        1.0,
+    );
+}
+
+pub fn record_run_finished() {
+    metrics().set(
+        "tv_series_bot_last_run_timestamp_seconds",
+        "Unix time when the last run finished.",
+        &[],
+        chrono::Utc::now().timestamp() as f64,
+    );
+}
+
+pub fn render() -> String {
+    metrics().render()
+}
+
+// For the node_exporter textfile collector: written to a temporary file
+// and renamed, so that the collector never reads a half-written file
+pub fn write_textfile(path: &str) -> Result<(), BotError> {
+    let temporary_path = format!("{}.tmp", path);
+    fs::write(&temporary_path, render()).map_err(|err| BotError::io(&temporary_path, err))?;
+    fs::rename(&temporary_path, path).map_err(|err| BotError::io(path, err))
+}
+
+pub async fn bind(listen: &str) -> Result<TcpListener, BotError> {
+    TcpListener::bind(listen)
+        .await
+        .map_err(|err| BotError::io(&format!("metrics listen {}", listen), err))
+}
+
+// Answer GET /metrics on the listener until the process exits
+pub async fn serve(listener: TcpListener) {
+    if let Ok(address) = listener.local_addr() {
+        info!("Serving metrics on http://{}/metrics", address);
+    }
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                error!("Cannot accept metrics connection: {}", err);
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            let mut buffer = [0; 1024];
+            let read = stream.read(&mut buffer).await.unwrap_or_default();
+            let request = String::from_utf8_lossy(&buffer[..read]);
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+            let (status, content_type, body) = if path == "/metrics" {
+                ("200 OK", "text/plain; version=0.0.4", render())
+            } else {
+                ("404 Not Found", "text/plain", String::from("not found\n"))
+            };
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                content_type,
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut metrics = Metrics::default();
+        metrics.add("posts_total", "Posts.", &[("channel", "mastodon")], 1.0);
+        metrics.add("posts_total", "Posts.", &[("channel", "mastodon")], 1.0);
+        metrics.add("posts_total", "Posts.", &[("channel", "say \"hi\"")], 1.0);
+        metrics.observe("fetch_seconds", "Fetch.", &[], 0.3);
+        metrics.observe("fetch_seconds", "Fetch.", &[], 120.0);
+        let text = metrics.render();
+        assert!(text.contains("# TYPE posts_total counter\n"));
+        assert!(text.contains("posts_total{channel=\"mastodon\"} 2\n"));
+        assert!(text.contains("posts_total{channel=\"say \\\"hi\\\"\"} 1\n"));
+        assert!(text.contains("# TYPE fetch_seconds histogram\n"));
+        assert!(text.contains("fetch_seconds_bucket{le=\"0.25\"} 0\n"));
+        assert!(text.contains("fetch_seconds_bucket{le=\"0.5\"} 1\n"));
+        assert!(text.contains("fetch_seconds_bucket{le=\"60\"} 1\n"));
+        assert!(text.contains("fetch_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("fetch_seconds_sum 120.3\n"));
+        assert!(text.contains("fetch_seconds_count 2\n"));
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        record_post("matrix", true);
+        let listener = bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener));
+        let client = reqwest::Client::new();
+        let response = client
+            .get(format!("http://{}/metrics", address))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = response.text().await.unwrap();
+        assert!(body.contains("tv_series_bot_posts_total{channel=\"matrix\",result=\"success\"}"));
+        let response = client
+            .get(format!("http://{}/", address))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+}
@@ -0,0 +1,838 @@
+use crate::apis::SeasonData;
+use crate::config::{Config, QueueConfig};
+use crate::daemon::{self, Scheduler};
+use crate::db::models::QueuedPost;
+use crate::db::PostHistory;
+use crate::error::BotError;
+use crate::logging;
+use crate::mastodon_instance;
+use crate::metrics;
+use crate::queue;
+use crate::registry::Registry;
+use crate::requests::{download_file, FileDownload};
+use crate::summary::RunSummary;
+use crate::utils;
+use chrono::NaiveDate;
+use futures::future::join_all;
+use futures::stream::{self, StreamExt};
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+pub struct PublishOptions {
+    pub send_to: Vec<String>,
+    // pause between two posts
+    pub interval: Duration,
+    // print the posts instead of publishing them
+    pub dry_run: bool,
+    // set on SIGTERM/SIGINT in daemon mode
+    pub shutdown: Arc<AtomicBool>,
+}
+
+impl PublishOptions {
+    // Publish to every channel from send_to, without pauses
+    pub fn new(config: &Config) -> Self {
+        Self {
+            send_to: config.send_to.clone(),
+            interval: Duration::ZERO,
+            dry_run: false,
+            shutdown: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+// Fetches new seasons from the sources and publishes them once per channel
+pub struct Pipeline<'a> {
+    config: &'a Config,
+    registry: Registry<'a>,
+    history: Mutex<PostHistory>,
+    // what the current run did so far
+    summary: Mutex<RunSummary>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new(config: &'a Config, registry: Registry<'a>, history: PostHistory) -> Self {
+        Self {
+            config,
+            registry,
+            history: Mutex::new(history),
+            summary: Mutex::new(RunSummary::default()),
+        }
+    }
+
+    // The lock is never held across an await
+    fn history(&self) -> MutexGuard<'_, PostHistory> {
+        self.history
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn summary(&self) -> MutexGuard<'_, RunSummary> {
+        self.summary
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // The summary of everything done since the last call
+    pub fn take_summary(&self) -> RunSummary {
+        std::mem::take(&mut *self.summary())
+    }
+
+    // Detected once per run, so that a daemon picks up changed limits of the
+    // instance once the cached ones have expired
+    async fn refresh_instance_limits(&self, options: &PublishOptions) {
+        let mastodon = &self.config.mastodon;
+        if !mastodon.detect_limits || !options.send_to.iter().any(|c| c == "mastodon") {
+            return;
+        }
+        let cache_file = self.config.instance_cache_file();
+        let limits = mastodon_instance::detect_limits(mastodon, cache_file.as_deref()).await;
+        mastodon.set_instance_limits(limits);
+    }
+
+    // Seasons from every source and every given day, each season only once.
+    // A day that cannot be fetched is left out, only when no day could be
+    // fetched the run fails.
+    pub async fn fetch_seasons(&self, dates: &[NaiveDate]) -> Result<Vec<SeasonData>, BotError> {
+        let mut new_seasons: Vec<SeasonData> = vec![];
+        let mut fetched_days = 0;
+        let mut last_error = None;
+        for source in self.registry.sources() {
+            let dates = match source.is_dated() {
+                true => dates,
+                false => &dates[dates.len().saturating_sub(1)..],
+            };
+            let mut days = stream::iter(dates.iter())
+                .map(|target_date| async move {
+                    let started = Instant::now();
+                    let schedule = source.seasons(*target_date).await;
+                    metrics::record_fetch(source.name(), started.elapsed());
+                    (target_date, schedule)
+                })
+                .buffered(self.config.download_workers);
+            while let Some((target_date, schedule)) = days.next().await {
+                let schedule = match schedule {
+                    Ok(schedule) => schedule,
+                    Err(err) => {
+                        error!(
+                            "Cannot fetch the {} schedule of {}: {}",
+                            source.name(),
+                            target_date,
+                            err
+                        );
+                        self.summary().days_failed += 1;
+                        last_error = Some(err);
+                        continue;
+                    }
+                };
+                fetched_days += 1;
+                let mut summary = self.summary();
+                summary.seasons_fetched += schedule.seasons.len();
+                for (reason, count) in schedule.skipped.iter() {
+                    summary.skip(reason, *count);
+                }
+                for season in schedule.seasons {
+                    let is_duplicate = new_seasons.iter().any(|s| {
+                        s.show_id == season.show_id && s.season_number == season.season_number
+                    });
+                    if is_duplicate {
+                        summary.skip("duplicate", 1);
+                        continue;
+                    }
+                    for genre in season.genres.iter() {
+                        if self.config.target_genres.contains(genre) {
+                            metrics::record_season(genre);
+                        }
+                    }
+                    logging::for_season_sync(&season, None, || {
+                        info!("Found {} season {}", season.title, season.season_number)
+                    });
+                    new_seasons.push(season);
+                }
+            }
+        }
+        match last_error {
+            Some(err) if fetched_days == 0 => Err(err),
+            _ => Ok(new_seasons),
+        }
+    }
+
+    fn image_download(&self, image_url: &str) -> FileDownload {
+        FileDownload {
+            download_url: image_url.to_string(),
+            save_folder: self.config.image_dir.clone(),
+            headers: self.registry.image_headers(),
+        }
+    }
+
+    // Download the images of the seasons, at most `download_workers` at a time.
+    // Returns the file path of every image url that could be downloaded.
+    async fn download_images(&self, seasons: &[&SeasonData]) -> HashMap<String, String> {
+        // seasons with the same image (e.g. queued for several channels) download it once
+        let mut image_seasons: Vec<(&String, &SeasonData)> = vec![];
+        for season in seasons.iter() {
+            if let Some(image_url) = &season.image_url {
+                if !image_seasons.iter().any(|(url, _)| *url == image_url) {
+                    image_seasons.push((image_url, season));
+                }
+            }
+        }
+        let image_paths: HashMap<String, String> = stream::iter(image_seasons.iter())
+            .map(|(image_url, season)| {
+                logging::for_season(season, None, async move {
+                    match download_file(self.image_download(image_url)).await {
+                        Ok(file_path) => Some(((*image_url).clone(), file_path)),
+                        Err(err) => {
+                            error!("Cannot download image {}: {}", image_url, err);
+                            None
+                        }
+                    }
+                })
+            })
+            .buffer_unordered(self.config.download_workers)
+            .filter_map(|downloaded| async move { downloaded })
+            .collect()
+            .await;
+        let mut summary = self.summary();
+        summary.images_downloaded += image_paths.len();
+        summary.images_failed += image_seasons.len() - image_paths.len();
+        image_paths
+    }
+
+    fn preview_season(&self, new_season: &SeasonData, send_to: &[String]) {
+        // show where the image would be saved, without downloading it
+        let image_path = new_season
+            .image_url
+            .as_ref()
+            .map(|image_url| self.image_download(image_url).file_path());
+        self.summary().previewed += 1;
+        for channel in send_to.iter() {
+            match self.registry.publisher(channel) {
+                Some(publisher) => {
+                    let preview = publisher.preview(new_season, image_path.clone());
+                    println!("{}\n", preview);
+                }
+                None => warn!("Unknown SendTo param: {:?}", channel),
+            }
+        }
+    }
+
+    async fn publish_to_channel(
+        &self,
+        channel: &str,
+        new_season: &SeasonData,
+        image_path: Option<String>,
+    ) -> Result<String, BotError> {
+        let publisher = match self.registry.publisher(channel) {
+            Some(publisher) => publisher,
+            None => {
+                warn!("Unknown SendTo param: {:?}", channel);
+                self.summary().failed(channel);
+                metrics::record_post(channel, false);
+                return Err(BotError::Config(format!("unknown channel {}", channel)));
+            }
+        };
+        match publisher.publish(new_season, image_path).await {
+            Ok(post_id) => {
+                self.summary().posted(channel);
+                metrics::record_post(channel, true);
+                Ok(post_id)
+            }
+            Err(err) => {
+                error!("Cannot post to {}: {}", channel, err);
+                self.summary().failed(channel);
+                metrics::record_post(channel, false);
+                Err(err)
+            }
+        }
+    }
+
+    // Publish the posts of one channel in order, `interval` apart
+    async fn publish_channel(
+        &self,
+        channel: &str,
+        posts: Vec<(&SeasonData, Option<String>)>,
+        options: &PublishOptions,
+    ) {
+        for (i, (new_season, image_path)) in posts.into_iter().enumerate() {
+            if i > 0 {
+                daemon::sleep(options.interval, &options.shutdown).await;
+            }
+            if daemon::is_shutting_down(&options.shutdown) {
+                info!(
+                    "Shutting down, {} is not published to {}",
+                    new_season.title, channel
+                );
+                return;
+            }
+            logging::for_season(new_season, Some(channel), async {
+                let post_id = self
+                    .publish_to_channel(channel, new_season, image_path)
+                    .await;
+                if let Ok(post_id) = post_id {
+                    let recorded = self.history().record(new_season, channel, &post_id);
+                    if let Err(err) = recorded {
+                        error!("Cannot save {} to posting history: {}", post_id, err);
+                    }
+                }
+            })
+            .await;
+        }
+    }
+
+    // The images are downloaded concurrently, then every channel publishes
+    // its posts in the order of the seasons, in parallel with the other channels
+    pub async fn publish_seasons(&self, new_shows: &[SeasonData], options: &PublishOptions) {
+        let mut pending: Vec<(&SeasonData, Vec<String>)> = vec![];
+        for new_season in new_shows.iter() {
+            let channels = pending_channels(&mut self.history(), &options.send_to, new_season);
+            if channels.is_empty() {
+                self.summary().skip("already published", 1);
+            } else {
+                pending.push((new_season, channels));
+            }
+        }
+        if options.dry_run {
+            for (new_season, channels) in pending.iter() {
+                self.preview_season(new_season, channels);
+            }
+            return;
+        }
+        let seasons: Vec<&SeasonData> = pending.iter().map(|(season, _)| *season).collect();
+        let image_paths = self.download_images(&seasons).await;
+        let channel_posts = options.send_to.iter().map(|channel| {
+            let posts = pending
+                .iter()
+                .filter(|(_, channels)| channels.contains(channel))
+                .map(|(new_season, _)| (*new_season, image_path(&image_paths, new_season)))
+                .collect();
+            self.publish_channel(channel, posts, options)
+        });
+        join_all(channel_posts).await;
+    }
+
+    // Add the seasons that are neither published nor queued yet to the posting queue
+    fn enqueue_seasons(
+        &self,
+        queue_config: &QueueConfig,
+        new_shows: &[SeasonData],
+        options: &PublishOptions,
+    ) -> Result<(), BotError> {
+        let mut history = self.history();
+        let mut new_posts: Vec<(&SeasonData, Vec<String>)> = vec![];
+        let mut summary = self.summary();
+        for new_season in new_shows.iter() {
+            let pending = pending_channels(&mut history, &options.send_to, new_season);
+            if pending.is_empty() {
+                summary.skip("already published", 1);
+                continue;
+            }
+            let mut channels = vec![];
+            for channel in pending {
+                if !history.is_queued(new_season, &channel)? {
+                    channels.push(channel);
+                }
+            }
+            if channels.is_empty() {
+                summary.skip("already queued", 1);
+            } else {
+                new_posts.push((new_season, channels));
+            }
+        }
+        summary.queued += new_posts.len();
+        let last_queued_at = history.last_queued_at()?;
+        let publish_times = queue::plan_publish_times(
+            queue_config,
+            self.config.tz,
+            chrono::Utc::now(),
+            last_queued_at,
+            new_posts.len(),
+        );
+        for ((new_season, channels), publish_at) in new_posts.iter().zip(publish_times) {
+            for channel in channels.iter() {
+                history.enqueue(new_season, channel, publish_at)?;
+            }
+            logging::for_season_sync(new_season, None, || {
+                info!(
+                    "{} season {} is queued for {}",
+                    new_season.title,
+                    new_season.season_number,
+                    publish_at.with_timezone(&self.config.tz).to_rfc3339()
+                )
+            });
+        }
+        Ok(())
+    }
+
+    // Publish the queued posts of one channel in order
+    async fn publish_queued(
+        &self,
+        posts: Vec<(&QueuedPost, &SeasonData, Option<String>)>,
+        options: &PublishOptions,
+    ) {
+        for (queued, new_season, image_path) in posts {
+            if daemon::is_shutting_down(&options.shutdown) {
+                info!("Shutting down, {} stays queued", new_season.title);
+                return;
+            }
+            logging::for_season(new_season, Some(&queued.channel), async {
+                let post_id = self
+                    .publish_to_channel(&queued.channel, new_season, image_path)
+                    .await;
+                let saved = match post_id {
+                    Ok(ref post_id) => self.history().record_queued(queued, post_id),
+                    Err(err)
+                        if err.is_permanent() || queued.attempts + 1 >= queue::MAX_ATTEMPTS =>
+                    {
+                        error!(
+                            "Giving up {} for {} after {} attempts",
+                            new_season.title,
+                            queued.channel,
+                            queued.attempts + 1
+                        );
+                        self.history().fail_queued(queued)
+                    }
+                    // failed posts stay in the queue and are tried again later
+                    Err(_) => self.history().postpone(queued, self.retry_time()),
+                };
+                if let Err(err) = saved {
+                    error!("Cannot update the posting queue: {}", err);
+                }
+            })
+            .await;
+        }
+    }
+
+    // When a failed queued post is tried again, inside the publishing window
+    fn retry_time(&self) -> chrono::DateTime<chrono::Utc> {
+        let retry_at = chrono::Utc::now() + chrono::Duration::minutes(queue::RETRY_DELAY_MINUTES);
+        match &self.config.queue {
+            Some(queue_config) => {
+                queue::plan_publish_times(queue_config, self.config.tz, retry_at, None, 1)
+                    .into_iter()
+                    .next()
+                    .unwrap_or(retry_at)
+            }
+            None => retry_at,
+        }
+    }
+
+    // Publish the queued posts whose time has come, the channels in parallel
+    pub async fn publish_due_posts(&self, options: &PublishOptions) -> Result<(), BotError> {
+        let due_posts = self.history().due_posts(chrono::Utc::now())?;
+        let mut seasons = vec![];
+        for queued in due_posts.iter() {
+            seasons.push(queued.season()?);
+        }
+        let season_refs: Vec<&SeasonData> = seasons.iter().collect();
+        let image_paths = self.download_images(&season_refs).await;
+        let mut channels: Vec<&String> = due_posts.iter().map(|queued| &queued.channel).collect();
+        channels.sort();
+        channels.dedup();
+        let channel_posts = channels.into_iter().map(|channel| {
+            let posts = due_posts
+                .iter()
+                .zip(seasons.iter())
+                .filter(|(queued, _)| &queued.channel == channel)
+                .map(|(queued, new_season)| {
+                    (queued, new_season, image_path(&image_paths, new_season))
+                })
+                .collect();
+            self.publish_queued(posts, options)
+        });
+        join_all(channel_posts).await;
+        Ok(())
+    }
+
+    // Publish (or queue) the new seasons of today and of the lookback days,
+    // see `take_summary` for what was published
+    pub async fn run(&self, options: &PublishOptions) -> Result<(), BotError> {
+        self.refresh_instance_limits(options).await;
+        let today = utils::today(&self.config.tz);
+        let dates = utils::lookback_dates(today, self.config.lookback_days);
+        let new_shows = self.fetch_seasons(&dates).await?;
+        match &self.config.queue {
+            Some(queue_config) if !options.dry_run => {
+                self.enqueue_seasons(queue_config, &new_shows, options)?;
+                self.publish_due_posts(options).await
+            }
+            _ => {
+                self.publish_seasons(&new_shows, options).await;
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn backfill(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+        options: &PublishOptions,
+    ) -> Result<(), BotError> {
+        if from > to {
+            return Err(BotError::Config(format!(
+                "--from {} is after --to {}",
+                from, to
+            )));
+        }
+        self.refresh_instance_limits(options).await;
+        let new_shows = self.fetch_seasons(&utils::date_range(from, to)).await?;
+        info!(
+            "Backfilling {} seasons from {} to {}",
+            new_shows.len(),
+            from,
+            to
+        );
+        self.publish_seasons(&new_shows, options).await;
+        Ok(())
+    }
+
+    // Stay resident and run on the configured schedules until SIGTERM/SIGINT
+    pub async fn run_daemon(&self, options: &PublishOptions) -> Result<(), BotError> {
+        let config = self.config;
+        let scheduler = Scheduler::new(&config.daemon.schedules, config.tz)?;
+        loop {
+            let next_run = scheduler
+                .next_run(chrono::Utc::now())
+                .ok_or_else(|| {
+                    BotError::Config(String::from("daemon schedules have no upcoming runs"))
+                })?
+                .with_timezone(&chrono::Utc);
+            let next_queued_at = match config.queue {
+                Some(_) => self.history().next_queued_at()?,
+                None => None,
+            };
+            let wake_up_at = match next_queued_at {
+                Some(next_queued_at) if next_queued_at < next_run => {
+                    info!(
+                        "Next queued post at {}",
+                        next_queued_at.with_timezone(&config.tz).to_rfc3339()
+                    );
+                    next_queued_at
+                }
+                _ => {
+                    info!(
+                        "Next run at {}",
+                        next_run.with_timezone(&config.tz).to_rfc3339()
+                    );
+                    next_run
+                }
+            };
+            if !daemon::sleep_until(wake_up_at, &options.shutdown).await {
+                break;
+            }
+            logging::in_run(async {
+                let result = if wake_up_at < next_run {
+                    self.publish_due_posts(options).await
+                } else {
+                    // failed posts are not in the posting history and are retried on the next run
+                    self.run(options).await
+                };
+                let mut summary = self.take_summary();
+                if let Err(err) = result {
+                    error!("{}", err);
+                    summary.error = Some(err.to_string());
+                }
+                summary.report(config.summary_file.as_deref());
+                write_metrics(config);
+            })
+            .await;
+            if daemon::is_shutting_down(&options.shutdown) {
+                break;
+            }
+        }
+        info!("Shutting down");
+        Ok(())
+    }
+}
+
+// Rewrite the node_exporter textfile, if set
+pub fn write_metrics(config: &Config) {
+    metrics::record_run_finished();
+    if let Some(textfile) = &config.metrics.textfile {
+        if let Err(err) = metrics::write_textfile(textfile) {
+            error!("Cannot write metrics: {}", err);
+        }
+    }
+}
+
+fn image_path(image_paths: &HashMap<String, String>, new_season: &SeasonData) -> Option<String> {
+    let image_url = new_season.image_url.as_ref()?;
+    image_paths.get(image_url).cloned()
+}
+
+// Channels from send_to that have not announced this season yet
+fn pending_channels(
+    history: &mut PostHistory,
+    channels: &[String],
+    new_season: &SeasonData,
+) -> Vec<String> {
+    let mut pending = vec![];
+    for channel in channels.iter() {
+        match history.is_published(new_season, channel) {
+            Ok(true) => info!(
+                "{} season {} is already published to {}",
+                new_season.title, new_season.season_number, channel
+            ),
+            Ok(false) => pending.push(channel.clone()),
+            Err(err) => error!("Cannot read posting history: {}", err),
+        }
+    }
+    pending
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::{Schedule, Source};
+    use crate::preview::PostPreview;
+    use crate::publisher::Publisher;
+    use crate::summary::RunStatus;
+    use async_trait::async_trait;
+
+    fn season_data(show_id: i32) -> SeasonData {
+        SeasonData {
+            show_id,
+            title: format!("title {}", show_id),
+            url: String::from("url"),
+            language: None,
+            description: None,
+            genres: vec![],
+            image_url: None,
+            season_number: 1,
+            host: None,
+            airstamp: None,
+            airtime: None,
+        }
+    }
+
+    // The same seasons for every day
+    struct TestSource;
+
+    #[async_trait]
+    impl Source for TestSource {
+        fn name(&self) -> &str {
+            "test"
+        }
+
+        async fn seasons(&self, _: NaiveDate) -> Result<Schedule, BotError> {
+            Ok(Schedule::from(vec![season_data(1), season_data(2)]))
+        }
+    }
+
+    // Same seasons for every day, like a saved schedule
+    struct UndatedSource;
+
+    #[async_trait]
+    impl Source for UndatedSource {
+        fn name(&self) -> &str {
+            "file"
+        }
+
+        async fn seasons(&self, _: NaiveDate) -> Result<Schedule, BotError> {
+            Ok(Schedule::from(vec![season_data(1), season_data(2)]))
+        }
+
+        fn is_dated(&self) -> bool {
+            false
+        }
+    }
+
+    // Fails for the days before `fails_before`
+    struct FlakySource {
+        fails_before: NaiveDate,
+    }
+
+    #[async_trait]
+    impl Source for FlakySource {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        async fn seasons(&self, target_date: NaiveDate) -> Result<Schedule, BotError> {
+            if target_date < self.fails_before {
+                return Err(BotError::Parse(String::from("schedule")));
+            }
+            Ok(Schedule::from(vec![season_data(1)]))
+        }
+    }
+
+    struct TestPublisher<'a> {
+        published: &'a Mutex<Vec<i32>>,
+    }
+
+    #[async_trait]
+    impl<'a> Publisher for TestPublisher<'a> {
+        fn name(&self) -> &str {
+            "matrix"
+        }
+
+        async fn publish(&self, data: &SeasonData, _: Option<String>) -> Result<String, BotError> {
+            self.published.lock().unwrap().push(data.show_id);
+            Ok(data.show_id.to_string())
+        }
+
+        fn preview(&self, data: &SeasonData, image_path: Option<String>) -> PostPreview {
+            PostPreview {
+                channel: String::from("matrix"),
+                post_text: data.title.clone(),
+                text_length: 0,
+                max_length: 0,
+                image_path,
+                target_url: String::new(),
+                replies: vec![],
+            }
+        }
+    }
+
+    fn test_config() -> Config {
+        Config::new(
+            r#"
+            send_to = ["matrix", "unknown"]
+            target_genres = ["Drama"]
+            image_dir = "/tmp/"
+            database_url = ":memory:"
+            lookback_days = 2
+
+            [mastodon]
+            token = "mastodon token"
+            url = "https://your.mastodon.instance"
+            image_api_url = "https://your.mastodon.instance/api/v2/media"
+            max_post_len = 500
+
+            [telegram]
+            token = "telegram token"
+            chat_id = "telegram chat id"
+        "#,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_run_publishes_once() {
+        let config = test_config();
+        let published = Mutex::new(vec![]);
+        let mut registry = Registry::new();
+        registry.add_source(Box::new(TestSource));
+        registry.add_publisher(Box::new(TestPublisher {
+            published: &published,
+        }));
+        let history = PostHistory::open(":memory:").unwrap();
+        let pipeline = Pipeline::new(&config, registry, history);
+        let options = PublishOptions::new(&config);
+        pipeline.run(&options).await.unwrap();
+        assert_eq!(*published.lock().unwrap(), vec![1, 2]);
+        let summary = pipeline.take_summary();
+        // every season is returned for each of the 3 days
+        assert_eq!(summary.seasons_fetched, 6);
+        assert_eq!(summary.skipped["duplicate"], 4);
+        assert_eq!(summary.channels["matrix"].posted, 2);
+        assert_eq!(summary.channels["unknown"].failed, 2);
+        assert_eq!(summary.status(), RunStatus::PartialFailure);
+        // already in the posting history
+        pipeline.run(&options).await.unwrap();
+        assert_eq!(published.lock().unwrap().len(), 2);
+        let summary = pipeline.take_summary();
+        assert!(!summary.channels.contains_key("matrix"));
+        assert_eq!(summary.status(), RunStatus::TotalFailure);
+    }
+
+    #[tokio::test]
+    async fn test_undated_source_is_fetched_once() {
+        let config = test_config();
+        let mut registry = Registry::new();
+        registry.add_source(Box::new(UndatedSource));
+        let history = PostHistory::open(":memory:").unwrap();
+        let pipeline = Pipeline::new(&config, registry, history);
+        let today = utils::today(&config.tz);
+        let dates = utils::lookback_dates(today, 2);
+        let seasons = pipeline.fetch_seasons(&dates).await.unwrap();
+        assert_eq!(seasons.len(), 2);
+        let summary = pipeline.take_summary();
+        assert_eq!(summary.seasons_fetched, 2);
+        assert!(!summary.skipped.contains_key("duplicate"));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_does_not_publish() {
+        let config = test_config();
+        let published = Mutex::new(vec![]);
+        let mut registry = Registry::new();
+        registry.add_source(Box::new(TestSource));
+        registry.add_publisher(Box::new(TestPublisher {
+            published: &published,
+        }));
+        let history = PostHistory::open(":memory:").unwrap();
+        let pipeline = Pipeline::new(&config, registry, history);
+        let mut options = PublishOptions::new(&config);
+        options.dry_run = true;
+        pipeline.run(&options).await.unwrap();
+        assert!(published.lock().unwrap().is_empty());
+        assert_eq!(pipeline.take_summary().previewed, 2);
+        // a season published to every channel is not previewed again
+        for channel in ["matrix", "unknown"] {
+            pipeline
+                .history()
+                .record(&season_data(1), channel, "1")
+                .unwrap();
+        }
+        pipeline.run(&options).await.unwrap();
+        let summary = pipeline.take_summary();
+        assert_eq!(summary.previewed, 1);
+        assert_eq!(summary.skipped["already published"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_queued_post_to_unknown_channel_is_given_up() {
+        let mut config = test_config();
+        config.queue = Some(QueueConfig {
+            window_start: None,
+            window_end: None,
+            min_interval: Some(3600),
+        });
+        let published = Mutex::new(vec![]);
+        let mut registry = Registry::new();
+        registry.add_source(Box::new(TestSource));
+        registry.add_publisher(Box::new(TestPublisher {
+            published: &published,
+        }));
+        let history = PostHistory::open(":memory:").unwrap();
+        let pipeline = Pipeline::new(&config, registry, history);
+        let options = PublishOptions::new(&config);
+        pipeline.run(&options).await.unwrap();
+        assert_eq!(*published.lock().unwrap(), vec![1]);
+        let mut history = pipeline.history();
+        // not tried again, and not queued again by the next run
+        assert!(history.due_posts(chrono::Utc::now()).unwrap().is_empty());
+        assert!(history.is_queued(&season_data(1), "unknown").unwrap());
+        assert!(!history.is_queued(&season_data(1), "matrix").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_failed_day_is_skipped() {
+        let config = test_config();
+        let today = utils::today(&config.tz);
+        let mut registry = Registry::new();
+        registry.add_source(Box::new(FlakySource {
+            fails_before: today,
+        }));
+        let history = PostHistory::open(":memory:").unwrap();
+        let pipeline = Pipeline::new(&config, registry, history);
+        let dates = utils::lookback_dates(today, config.lookback_days);
+        let seasons = pipeline.fetch_seasons(&dates).await.unwrap();
+        assert_eq!(seasons.len(), 1);
+        let summary = pipeline.take_summary();
+        assert_eq!(summary.days_failed, 2);
+        assert_eq!(summary.status(), RunStatus::PartialFailure);
+        // every day failed
+        let mut registry = Registry::new();
+        registry.add_source(Box::new(FlakySource {
+            fails_before: today + chrono::Duration::days(1),
+        }));
+        let history = PostHistory::open(":memory:").unwrap();
+        let pipeline = Pipeline::new(&config, registry, history);
+        assert!(pipeline.fetch_seasons(&dates).await.is_err());
+    }
+}
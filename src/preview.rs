@@ -0,0 +1,87 @@
+use crate::mastodon::MastodonPost;
+use crate::requests::RequestData;
+use crate::telegram::TelegramPost;
+use std::fmt;
+
+// Rendered post that would be sent to a channel in dry-run mode
+#[derive(Debug)]
+pub struct PostPreview {
+    pub channel: String,
+    pub post_text: String,
+    pub text_length: i32,
+    pub max_length: i32,
+    pub image_path: Option<String>,
+    pub target_url: String,
+    // the next statuses of a mastodon thread
+    pub replies: Vec<PostPreview>,
+}
+
+impl PostPreview {
+    pub fn from_mastodon_post(post: &MastodonPost, image_path: Option<String>) -> Self {
+        Self {
+            channel: String::from("mastodon"),
+            post_text: post.post_text.clone(),
+            text_length: post.text_length(),
+            max_length: post.config.limits().max_characters,
+            image_path,
+            target_url: post.url(),
+            replies: vec![],
+        }
+    }
+
+    pub fn from_telegram_post(post: &TelegramPost) -> Self {
+        // do not print the bot token
+        let target_url = post.url().replace(&post.config.token, "<token>");
+        Self {
+            channel: String::from("telegram"),
+            post_text: post.post_text.clone(),
+            text_length: post.post_text.chars().count() as i32,
+            max_length: post.max_length(),
+            image_path: post.image_path.clone(),
+            target_url,
+            replies: vec![],
+        }
+    }
+}
+
+impl fmt::Display for PostPreview {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let image_path = self.image_path.as_deref().unwrap_or("N/A");
+        writeln!(f, "----- {} -----", self.channel)?;
+        writeln!(f, "Target: {}", self.target_url)?;
+        writeln!(f, "Image: {}", image_path)?;
+        writeln!(f, "Length: {}/{}", self.text_length, self.max_length)?;
+        writeln!(f)?;
+        write!(f, "{}", self.post_text)?;
+        for reply in self.replies.iter() {
+            writeln!(f)?;
+            writeln!(f)?;
+            write!(f, "{}", reply)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let preview = PostPreview {
+            channel: String::from("mastodon"),
+            post_text: String::from("title\nurl"),
+            text_length: 9,
+            max_length: 500,
+            image_path: None,
+            target_url: String::from("https://your.mastodon.instance/api/v1/statuses"),
+            replies: vec![],
+        };
+        let expected = "----- mastodon -----\n\
+            Target: https://your.mastodon.instance/api/v1/statuses\n\
+            Image: N/A\n\
+            Length: 9/500\n\n\
+            title\nurl";
+        assert_eq!(preview.to_string(), expected);
+    }
+}
@@ -0,0 +1,19 @@
+use crate::apis::SeasonData;
+use crate::error::BotError;
+use crate::preview::PostPreview;
+use async_trait::async_trait;
+
+// Destination of the posts, e.g. a mastodon account or a telegram chat
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    // channel name used in `send_to` and in the posting history
+    fn name(&self) -> &str;
+    // Publish the post and return its id on the platform
+    async fn publish(
+        &self,
+        data: &SeasonData,
+        image_path: Option<String>,
+    ) -> Result<String, BotError>;
+    // The post that would be published, for dry runs
+    fn preview(&self, data: &SeasonData, image_path: Option<String>) -> PostPreview;
+}
@@ -0,0 +1,175 @@
+use crate::config::QueueConfig;
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+// Delay before a queued post that failed is tried again
+pub const RETRY_DELAY_MINUTES: i64 = 30;
+
+// Publishing attempts before a queued post is given up
+pub const MAX_ATTEMPTS: i32 = 5;
+
+// Publish times for `count` new posts: spread evenly over the publishing window
+// of the day (moving to the next day once the window is over) and at least
+// `min_interval` apart, after the posts that are already queued
+pub fn plan_publish_times(
+    config: &QueueConfig,
+    timezone: Tz,
+    now: DateTime<Utc>,
+    last_queued_at: Option<DateTime<Utc>>,
+    count: usize,
+) -> Vec<DateTime<Utc>> {
+    if count == 0 {
+        return vec![];
+    }
+    let min_interval = Duration::seconds(config.min_interval.unwrap_or(0) as i64);
+    // the first new post goes one interval after the last queued one
+    let (mut start, first_slot) = match last_queued_at {
+        Some(last_queued_at) if last_queued_at >= now => (last_queued_at, 1),
+        _ => (now, 0),
+    };
+    let Some((window_start, window_end)) = config.window() else {
+        return (0..count)
+            .map(|i| start + min_interval * (i + first_slot) as i32)
+            .collect();
+    };
+    let mut date = start.with_timezone(&timezone).date_naive();
+    let mut end = local_time(timezone, date, window_end);
+    if start >= end {
+        date = date.succ_opt().unwrap_or(date);
+        start = local_time(timezone, date, window_start);
+        end = local_time(timezone, date, window_end);
+    }
+    start = start.max(local_time(timezone, date, window_start));
+    let spacing = min_interval.max((end - start) / (count + first_slot) as i32);
+    let mut publish_at = start + spacing * first_slot as i32;
+    let mut planned = Vec::with_capacity(count);
+    while planned.len() < count {
+        if publish_at >= end {
+            date = date.succ_opt().unwrap_or(date);
+            publish_at = local_time(timezone, date, window_start);
+            end = local_time(timezone, date, window_end);
+        }
+        planned.push(publish_at);
+        publish_at += spacing;
+    }
+    planned
+}
+
+fn local_time(timezone: Tz, date: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+    let local = date.and_time(time);
+    timezone
+        .from_local_datetime(&local)
+        .earliest()
+        // the time is skipped by a daylight saving change, take the first one after the gap
+        .or_else(|| {
+            timezone
+                .from_local_datetime(&(local + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|| local.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue_config(window: Option<(&str, &str)>, min_interval: Option<u64>) -> QueueConfig {
+        QueueConfig {
+            window_start: window.map(|w| w.0.to_string()),
+            window_end: window.map(|w| w.1.to_string()),
+            min_interval,
+        }
+    }
+
+    fn times(planned: Vec<DateTime<Utc>>) -> Vec<String> {
+        planned
+            .iter()
+            .map(|t| t.format("%d %H:%M").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_window() {
+        let config = queue_config(Some(("09:00", "21:00")), None);
+        let now = Utc.with_ymd_and_hms(2023, 11, 2, 6, 0, 0).unwrap();
+        let planned = plan_publish_times(&config, chrono_tz::UTC, now, None, 4);
+        assert_eq!(
+            times(planned),
+            vec!["02 09:00", "02 12:00", "02 15:00", "02 18:00"]
+        );
+    }
+
+    #[test]
+    fn test_window_timezone() {
+        let config = queue_config(Some(("09:00", "21:00")), None);
+        let now = Utc.with_ymd_and_hms(2023, 11, 2, 6, 0, 0).unwrap();
+        let planned = plan_publish_times(&config, chrono_tz::Europe::Berlin, now, None, 2);
+        assert_eq!(times(planned), vec!["02 08:00", "02 14:00"]);
+    }
+
+    #[test]
+    fn test_window_started() {
+        let config = queue_config(Some(("09:00", "21:00")), None);
+        let now = Utc.with_ymd_and_hms(2023, 11, 2, 17, 0, 0).unwrap();
+        let planned = plan_publish_times(&config, chrono_tz::UTC, now, None, 2);
+        assert_eq!(times(planned), vec!["02 17:00", "02 19:00"]);
+    }
+
+    #[test]
+    fn test_window_over() {
+        let config = queue_config(Some(("09:00", "21:00")), None);
+        let now = Utc.with_ymd_and_hms(2023, 11, 2, 22, 0, 0).unwrap();
+        let planned = plan_publish_times(&config, chrono_tz::UTC, now, None, 2);
+        assert_eq!(times(planned), vec!["03 09:00", "03 15:00"]);
+    }
+
+    #[test]
+    fn test_after_queued() {
+        let config = queue_config(Some(("09:00", "21:00")), None);
+        let now = Utc.with_ymd_and_hms(2023, 11, 2, 9, 0, 0).unwrap();
+        let last_queued_at = Utc.with_ymd_and_hms(2023, 11, 2, 15, 0, 0).unwrap();
+        let planned = plan_publish_times(&config, chrono_tz::UTC, now, Some(last_queued_at), 2);
+        assert_eq!(times(planned), vec!["02 17:00", "02 19:00"]);
+    }
+
+    #[test]
+    fn test_after_queued_next_day() {
+        let config = queue_config(Some(("09:00", "21:00")), Some(3600));
+        let now = Utc.with_ymd_and_hms(2023, 11, 2, 9, 0, 0).unwrap();
+        let last_queued_at = Utc.with_ymd_and_hms(2023, 11, 2, 20, 0, 0).unwrap();
+        let planned = plan_publish_times(&config, chrono_tz::UTC, now, Some(last_queued_at), 2);
+        assert_eq!(times(planned), vec!["03 09:00", "03 10:00"]);
+    }
+
+    #[test]
+    fn test_min_interval() {
+        let config = queue_config(None, Some(1800));
+        let now = Utc.with_ymd_and_hms(2023, 11, 2, 9, 0, 0).unwrap();
+        let planned = plan_publish_times(&config, chrono_tz::UTC, now, None, 3);
+        assert_eq!(times(planned), vec!["02 09:00", "02 09:30", "02 10:00"]);
+        let last_queued_at = Utc.with_ymd_and_hms(2023, 11, 2, 10, 0, 0).unwrap();
+        let planned = plan_publish_times(&config, chrono_tz::UTC, now, Some(last_queued_at), 1);
+        assert_eq!(times(planned), vec!["02 10:30"]);
+    }
+
+    #[test]
+    fn test_window_with_min_interval() {
+        let config = queue_config(Some(("09:00", "10:00")), Some(3600));
+        let now = Utc.with_ymd_and_hms(2023, 11, 2, 6, 0, 0).unwrap();
+        let planned = plan_publish_times(&config, chrono_tz::UTC, now, None, 3);
+        assert_eq!(times(planned), vec!["02 09:00", "03 09:00", "04 09:00"]);
+    }
+
+    #[test]
+    fn test_window_in_daylight_saving_gap() {
+        // 02:30 does not exist in Berlin on 26 March 2023, the clocks go from 02:00 to 03:00
+        let date = NaiveDate::from_ymd_opt(2023, 3, 26).unwrap();
+        let time = NaiveTime::from_hms_opt(2, 30, 0).unwrap();
+        let publish_at = local_time(chrono_tz::Europe::Berlin, date, time);
+        assert_eq!(
+            publish_at,
+            Utc.with_ymd_and_hms(2023, 3, 26, 1, 30, 0).unwrap()
+        );
+    }
+}
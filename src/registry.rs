@@ -0,0 +1,143 @@
+use crate::apis::{Source, TvMaze};
+use crate::config::Config;
+use crate::mastodon::MastodonPublisher;
+use crate::publisher::Publisher;
+use crate::telegram::TelegramPublisher;
+use crate::utils;
+use reqwest::header::HeaderMap;
+
+// Sources of new seasons and publishers of the posts, looked up by name
+#[derive(Default)]
+pub struct Registry<'a> {
+    sources: Vec<Box<dyn Source + 'a>>,
+    publishers: Vec<Box<dyn Publisher + 'a>>,
+}
+
+impl<'a> Registry<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The tvmaze schedule and every publisher from the config
+    pub fn from_config(config: &'a Config) -> Self {
+        let mut registry = Self::new();
+        registry.add_source(Box::new(TvMaze::new(
+            utils::today(&config.tz),
+            &config.target_genres,
+        )));
+        registry.add_publisher(Box::new(MastodonPublisher::new(&config.mastodon)));
+        registry.add_publisher(Box::new(TelegramPublisher::new(&config.telegram)));
+        registry
+    }
+
+    pub fn add_source(&mut self, source: Box<dyn Source + 'a>) {
+        self.sources.push(source);
+    }
+
+    pub fn set_sources(&mut self, sources: Vec<Box<dyn Source + 'a>>) {
+        self.sources = sources;
+    }
+
+    // A publisher replaces the one with the same name
+    pub fn add_publisher(&mut self, publisher: Box<dyn Publisher + 'a>) {
+        self.publishers
+            .retain(|registered| registered.name() != publisher.name());
+        self.publishers.push(publisher);
+    }
+
+    pub fn sources(&self) -> &[Box<dyn Source + 'a>] {
+        &self.sources
+    }
+
+    pub fn publisher(&self, name: &str) -> Option<&(dyn Publisher + 'a)> {
+        self.publishers
+            .iter()
+            .find(|publisher| publisher.name() == name)
+            .map(|publisher| publisher.as_ref())
+    }
+
+    // Headers for the image downloads, from the first source
+    pub fn image_headers(&self) -> HeaderMap {
+        self.sources
+            .first()
+            .map(|source| source.image_headers())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::SeasonData;
+    use crate::error::BotError;
+    use crate::preview::PostPreview;
+    use async_trait::async_trait;
+
+    struct TestPublisher {
+        name: String,
+        post_id: String,
+    }
+
+    #[async_trait]
+    impl Publisher for TestPublisher {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        async fn publish(&self, _: &SeasonData, _: Option<String>) -> Result<String, BotError> {
+            Ok(self.post_id.clone())
+        }
+
+        fn preview(&self, data: &SeasonData, image_path: Option<String>) -> PostPreview {
+            PostPreview {
+                channel: self.name.clone(),
+                post_text: data.title.clone(),
+                text_length: data.title.chars().count() as i32,
+                max_length: 100,
+                image_path,
+                target_url: String::new(),
+                replies: vec![],
+            }
+        }
+    }
+
+    fn test_publisher(name: &str, post_id: &str) -> Box<TestPublisher> {
+        Box::new(TestPublisher {
+            name: name.to_string(),
+            post_id: post_id.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_publisher_lookup() {
+        let mut registry = Registry::new();
+        registry.add_publisher(test_publisher("matrix", "1"));
+        registry.add_publisher(test_publisher("bluesky", "2"));
+        assert_eq!(registry.publisher("bluesky").unwrap().name(), "bluesky");
+        assert!(registry.publisher("mastodon").is_none());
+        assert!(registry.image_headers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_publisher_replaced_by_name() {
+        let mut registry = Registry::new();
+        registry.add_publisher(test_publisher("matrix", "1"));
+        registry.add_publisher(test_publisher("matrix", "2"));
+        assert_eq!(registry.publishers.len(), 1);
+        let season = SeasonData {
+            show_id: 1,
+            title: String::from("title"),
+            url: String::from("url"),
+            language: None,
+            description: None,
+            genres: vec![],
+            image_url: None,
+            season_number: 1,
+            host: None,
+            airstamp: None,
+            airtime: None,
+        };
+        let publisher = registry.publisher("matrix").unwrap();
+        assert_eq!(publisher.publish(&season, None).await.unwrap(), "2");
+    }
+}
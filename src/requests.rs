@@ -1,10 +1,37 @@
+use crate::config::{HttpConfig, RetryConfig};
+use crate::error::BotError;
+use crate::metrics;
+use log::warn;
 use reqwest;
+use reqwest::multipart::{Form, Part};
+use reqwest::{Client, RequestBuilder, Response};
 use std::collections::HashMap;
-use std::error::Error;
 use std::fs;
-use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
 
-pub trait RequestData {
+// Directory where raw api responses are saved, if set
+static RECORD_DIR: OnceLock<PathBuf> = OnceLock::new();
+// Number of the next recorded response, responses received in the same millisecond get different files
+static RECORD_SEQUENCE: AtomicUsize = AtomicUsize::new(1);
+// Retry policy from the config, the default one if not set
+static RETRY_CONFIG: OnceLock<RetryConfig> = OnceLock::new();
+// Client shared by all requests, so that connections and TLS sessions are reused
+static CLIENT: OnceLock<Client> = OnceLock::new();
+// Timeouts and the settings the client was built with
+static HTTP_CONFIG: OnceLock<HttpConfig> = OnceLock::new();
+
+// Kind of request, each one has its own timeout
+#[derive(Clone, Copy)]
+enum Operation {
+    Api,
+    Download,
+    Upload,
+}
+
+pub trait RequestData: Sync {
     fn url(&self) -> String;
     fn params(&self) -> Vec<(String, String)> {
         vec![]
@@ -12,13 +39,18 @@ pub trait RequestData {
     fn headers(&self) -> reqwest::header::HeaderMap {
         reqwest::header::HeaderMap::new()
     }
-    fn json_multipart(&self) -> reqwest::blocking::multipart::Form {
-        reqwest::blocking::multipart::Form::new()
+    fn json_multipart(&self) -> Result<Form, BotError> {
+        Ok(Form::new())
     }
 
     fn json_body(&self) -> HashMap<String, String> {
-        let body = HashMap::new();
-        body
+        HashMap::new()
+    }
+
+    // Whether a POST can be sent again when it is unknown if the server got it,
+    // e.g. after a timeout; GET requests always can
+    fn idempotent(&self) -> bool {
+        false
     }
 }
 
@@ -30,11 +62,15 @@ pub struct FileDownload {
 
 impl FileDownload {
     pub fn file_path(&self) -> String {
-        let file_name = self.download_url.split('/').last().unwrap();
+        let file_name = self.download_url.split('/').next_back().unwrap();
         format!("{}{}", self.save_folder, file_name)
     }
     pub fn file_name(&self) -> String {
-        self.download_url.split('/').last().unwrap().to_string()
+        self.download_url
+            .split('/')
+            .next_back()
+            .unwrap()
+            .to_string()
     }
 }
 
@@ -46,65 +82,538 @@ pub struct FileUpload {
     pub params: Vec<(String, String)>,
 }
 
-pub fn get<T: RequestData>(data: &T) -> Result<String, Box<dyn Error>> {
-    let client = reqwest::blocking::Client::new();
-    let response = client
-        .get(data.url())
-        .headers(data.headers())
-        .query(&data.params())
-        .send()?;
-    let body = response.text()?;
-    Ok(body)
+// Creates the directory if it does not exist yet
+pub fn set_record_dir(record_dir: &str) -> Result<(), BotError> {
+    fs::create_dir_all(record_dir).map_err(|err| BotError::io(record_dir, err))?;
+    let _ = RECORD_DIR.set(PathBuf::from(record_dir));
+    Ok(())
 }
 
-pub fn post_multipart<T: RequestData>(data: &T) -> Result<String, Box<dyn Error>> {
-    let client = reqwest::blocking::Client::new();
-    let response = client
-        .post(data.url())
-        .headers(data.headers())
-        .query(&data.params())
-        .multipart(data.json_multipart())
-        .send()?;
-    let body = response.text()?;
-    Ok(body)
+pub fn set_retry_config(retry_config: RetryConfig) {
+    let _ = RETRY_CONFIG.set(retry_config);
 }
 
-pub fn post_json<T: RequestData>(data: &T) -> Result<String, Box<dyn Error>> {
-    let client = reqwest::blocking::Client::new();
-    let response = client
-        .post(data.url())
-        .headers(data.headers())
-        .query(&data.params())
-        .json(&data.json_body())
-        .send()?;
-    let body = response.text()?;
-    Ok(body)
+// Build the shared client from the [http] config, before the first request
+pub fn set_http_config(http_config: HttpConfig) -> Result<(), BotError> {
+    let client = build_client(&http_config)?;
+    let _ = CLIENT.set(client);
+    let _ = HTTP_CONFIG.set(http_config);
+    Ok(())
+}
+
+fn http_config() -> &'static HttpConfig {
+    HTTP_CONFIG.get_or_init(HttpConfig::default)
+}
+
+fn build_client(http_config: &HttpConfig) -> Result<Client, BotError> {
+    let mut builder = Client::builder()
+        .user_agent(http_config.user_agent.as_str())
+        .connect_timeout(Duration::from_secs_f64(http_config.connect_timeout));
+    if let Some(proxy_url) = &http_config.proxy {
+        let proxy = reqwest::Proxy::all(proxy_url.as_str())
+            .map_err(|err| BotError::Config(format!("[http] proxy {}: {}", proxy_url, err)))?;
+        builder = builder.proxy(proxy);
+    }
+    if let Some(ca_bundle) = &http_config.ca_bundle {
+        let content = fs::read_to_string(ca_bundle).map_err(|err| BotError::io(ca_bundle, err))?;
+        let certificates = pem_certificates(&content);
+        if certificates.is_empty() {
+            return Err(BotError::Config(format!(
+                "[http] ca_bundle {} has no PEM certificates",
+                ca_bundle
+            )));
+        }
+        for certificate in certificates {
+            let certificate =
+                reqwest::Certificate::from_pem(certificate.as_bytes()).map_err(|err| {
+                    BotError::Config(format!("[http] ca_bundle {}: {}", ca_bundle, err))
+                })?;
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    builder
+        .build()
+        .map_err(|err| BotError::Config(format!("[http] cannot build the client: {}", err)))
+}
+
+// Every certificate of a PEM bundle, reqwest only reads one at a time
+fn pem_certificates(content: &str) -> Vec<String> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let mut certificates = vec![];
+    let mut rest = content;
+    while let (Some(start), Some(end)) = (rest.find("-----BEGIN CERTIFICATE-----"), rest.find(END))
+    {
+        if end < start {
+            rest = &rest[end + END.len()..];
+            continue;
+        }
+        certificates.push(rest[start..end + END.len()].to_string());
+        rest = &rest[end + END.len()..];
+    }
+    certificates
+}
+
+pub fn client() -> &'static Client {
+    CLIENT.get_or_init(|| build_client(http_config()).unwrap_or_default())
+}
+
+fn timeout(operation: Operation) -> Duration {
+    let http_config = http_config();
+    let seconds = match operation {
+        Operation::Api => http_config.api_total_timeout,
+        Operation::Download => http_config.download_total_timeout,
+        Operation::Upload => http_config.upload_total_timeout,
+    };
+    Duration::from_secs_f64(seconds)
+}
+
+// Multipart part with the content of an image file
+pub fn file_part(file_path: &str) -> Result<Part, BotError> {
+    let content = fs::read(file_path).map_err(|err| BotError::io(file_path, err))?;
+    let path = Path::new(file_path);
+    let file_name = path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mime_type = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    };
+    Part::bytes(content)
+        .file_name(file_name)
+        .mime_str(mime_type)
+        .map_err(BotError::from)
+}
+
+// Last path segment of the url, safe to log or save as it never contains tokens
+fn endpoint_name(url: &str) -> &str {
+    url.split('?')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('/')
+        .split('/')
+        .next_back()
+        .unwrap_or_default()
+}
+
+// Host of the url, a metrics label that does not grow with every image
+fn host_name(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_string()))
+        .unwrap_or_default()
+}
+
+// File name for a recorded response, e.g. 20231102T150000.123_0007_web_2023-11-01.json,
+// with the requested day of a schedule, as several days are fetched at the same time
+fn record_file_name(
+    url: &str,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    sequence: usize,
+) -> String {
+    let endpoint = endpoint_name(url);
+    let date = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            url.query_pairs()
+                .find(|(name, _)| name == "date")
+                .map(|(_, date)| date.into_owned())
+        })
+        .map(|date| {
+            let date: String = date
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+                .collect();
+            format!("_{}", date)
+        })
+        .unwrap_or_default();
+    format!(
+        "{}_{:04}_{}{}.json",
+        timestamp.format("%Y%m%dT%H%M%S%.3f"),
+        sequence,
+        endpoint,
+        date
+    )
+}
+
+fn record_response(url: &str, body: &str) {
+    let record_dir = match RECORD_DIR.get() {
+        Some(dir) => dir,
+        None => return,
+    };
+    let sequence = RECORD_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let file_path = record_dir.join(record_file_name(url, chrono::Utc::now(), sequence));
+    if let Err(err) = fs::write(&file_path, body) {
+        log::error!("Cannot record response to {}: {}", file_path.display(), err);
+    }
+}
+
+// Delay from a Retry-After header, in seconds or as an http date
+fn retry_after_header(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let retry_at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = retry_at.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+// Delay from the telegram error body, e.g. {"ok":false,"parameters":{"retry_after":5}}
+fn telegram_retry_after(body: &str) -> Option<Duration> {
+    let json_body: serde_json::Value = serde_json::from_str(body).ok()?;
+    let seconds = json_body["parameters"]["retry_after"].as_u64()?;
+    Some(Duration::from_secs(seconds))
+}
+
+// The server refused the request before handling it and tells to come back later
+fn is_rate_limited(response: &Response) -> bool {
+    response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS
+        || response
+            .headers()
+            .contains_key(reqwest::header::RETRY_AFTER)
+}
+
+// The delay asked by the server, never longer than max_delay, or the backoff
+fn retry_delay(
+    retry_config: &RetryConfig,
+    attempt: u32,
+    retry_after: Option<Duration>,
+) -> Duration {
+    let max_delay = Duration::from_secs_f64(retry_config.max_delay.max(0.0));
+    match retry_after {
+        Some(retry_after) => retry_after.min(max_delay),
+        None => retry_config.backoff(attempt),
+    }
+}
+
+// Send the request built by `build_request`, retrying network errors and
+// retryable statuses with exponential backoff or the delay the server asks for.
+// A request that is not idempotent is only sent again when it did not reach
+// the server or was refused by a rate limit, so that a post is not published
+// twice after a timeout or a 5xx from a gateway.
+async fn send_with_retry<F>(
+    url: &str,
+    idempotent: bool,
+    build_request: F,
+) -> Result<Response, BotError>
+where
+    F: Fn() -> Result<RequestBuilder, BotError>,
+{
+    let retry_config = RETRY_CONFIG.get().cloned().unwrap_or_default();
+    let endpoint = endpoint_name(url);
+    let mut attempt = 1;
+    loop {
+        let (error, retry_after) = match build_request()?.send().await {
+            // the last failed response is returned as is, so that its status is reported
+            Ok(response)
+                if !retry_config.is_retryable_status(response.status().as_u16())
+                    || attempt >= retry_config.attempts
+                    || (!idempotent && !is_rate_limited(&response)) =>
+            {
+                return Ok(response)
+            }
+            Ok(response) => {
+                let status = response.status();
+                let header_delay = retry_after_header(response.headers());
+                let body = response.text().await.unwrap_or_default();
+                let retry_after = header_delay.or_else(|| telegram_retry_after(&body));
+                (format!("{}: {}", status, body), retry_after)
+            }
+            Err(err) if err.is_builder() => return Err(err.into()),
+            Err(err) if !idempotent && !err.is_connect() => return Err(err.into()),
+            Err(err) if attempt >= retry_config.attempts => return Err(err.into()),
+            Err(err) => (err.without_url().to_string(), None),
+        };
+        let delay = retry_delay(&retry_config, attempt, retry_after);
+        metrics::record_retry(&host_name(url));
+        warn!(
+            "Attempt {}/{} to {} failed: {}, retrying in {:.1}s",
+            attempt,
+            retry_config.attempts,
+            endpoint,
+            error,
+            delay.as_secs_f64()
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
 }
 
-pub fn download_file(source_file: FileDownload) -> Result<String, Box<dyn Error>> {
-    let client = reqwest::blocking::Client::new();
-    let response = client
-        .get(&source_file.download_url)
-        .headers(source_file.headers.clone())
-        .send()?;
-    let mut file = fs::File::create(source_file.file_path())?;
-    let mut content = Cursor::new(response.bytes()?);
-    std::io::copy(&mut content, &mut file)?;
-    Ok(source_file.file_path())
-}
-
-pub fn upload_file(source_file: FileUpload) -> Result<String, Box<dyn Error>> {
-    let client = reqwest::blocking::Client::new();
-    let form = reqwest::blocking::multipart::Form::new()
-        .file("file", source_file.file_path.clone())?
-        .text("description", source_file.description.clone());
-    let response = client
-        .post(&source_file.upload_url)
-        .timeout(std::time::Duration::from_secs(60))
-        .headers(source_file.headers)
-        .query(&source_file.params)
-        .multipart(form)
-        .send()?;
-    let body = response.text()?;
+// Body of a successful response, BotError::HttpStatus for any other status
+async fn response_body(url: &str, response: Response) -> Result<String, BotError> {
+    let status = response.status();
+    // with the query, e.g. the date of a schedule
+    let response_url = response.url().to_string();
+    let body = response.text().await?;
+    record_response(&response_url, &body);
+    if !status.is_success() {
+        return Err(BotError::HttpStatus {
+            endpoint: endpoint_name(url).to_string(),
+            status: status.as_u16(),
+            body,
+        });
+    }
     Ok(body)
 }
+
+pub async fn get<T: RequestData>(data: &T) -> Result<String, BotError> {
+    let response = send_with_retry(&data.url(), true, || {
+        Ok(client()
+            .get(data.url())
+            .timeout(timeout(Operation::Api))
+            .headers(data.headers())
+            .query(&data.params()))
+    })
+    .await?;
+    response_body(&data.url(), response).await
+}
+
+pub async fn post_multipart<T: RequestData>(data: &T) -> Result<String, BotError> {
+    let response = send_with_retry(&data.url(), data.idempotent(), || {
+        Ok(client()
+            .post(data.url())
+            .timeout(timeout(Operation::Api))
+            .headers(data.headers())
+            .query(&data.params())
+            .multipart(data.json_multipart()?))
+    })
+    .await?;
+    response_body(&data.url(), response).await
+}
+
+pub async fn post_json<T: RequestData>(data: &T) -> Result<String, BotError> {
+    let response = send_with_retry(&data.url(), data.idempotent(), || {
+        Ok(client()
+            .post(data.url())
+            .timeout(timeout(Operation::Api))
+            .headers(data.headers())
+            .query(&data.params())
+            .json(&data.json_body()))
+    })
+    .await?;
+    response_body(&data.url(), response).await
+}
+
+pub async fn download_file(source_file: FileDownload) -> Result<String, BotError> {
+    let response = send_with_retry(&source_file.download_url, true, || {
+        Ok(client()
+            .get(&source_file.download_url)
+            .timeout(timeout(Operation::Download))
+            .headers(source_file.headers.clone()))
+    })
+    .await?;
+    if !response.status().is_success() {
+        return Err(BotError::HttpStatus {
+            endpoint: endpoint_name(&source_file.download_url).to_string(),
+            status: response.status().as_u16(),
+            body: String::new(),
+        });
+    }
+    let file_path = source_file.file_path();
+    let content = response.bytes().await?;
+    metrics::record_image_download(content.len());
+    tokio::fs::write(&file_path, content)
+        .await
+        .map_err(|err| BotError::io(&file_path, err))?;
+    Ok(file_path)
+}
+
+pub async fn upload_file(source_file: FileUpload) -> Result<String, BotError> {
+    let response = send_with_retry(&source_file.upload_url, false, || {
+        let form = Form::new()
+            .part("file", file_part(&source_file.file_path)?)
+            .text("description", source_file.description.clone());
+        Ok(client()
+            .post(&source_file.upload_url)
+            .timeout(timeout(Operation::Upload))
+            .headers(source_file.headers.clone())
+            .query(&source_file.params)
+            .multipart(form))
+    })
+    .await?;
+    response_body(&source_file.upload_url, response).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    struct TestRequest {
+        url: String,
+    }
+
+    impl RequestData for TestRequest {
+        fn url(&self) -> String {
+            self.url.clone()
+        }
+    }
+
+    // Serve the given raw http responses, one per connection
+    fn serve(responses: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/test", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buffer = [0; 4096];
+                let _ = stream.read(&mut buffer);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_pem_certificates() {
+        let bundle =
+            "# internal CA\n-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n\
+            -----BEGIN CERTIFICATE-----\nMIIC\n-----END CERTIFICATE-----\n";
+        let certificates = pem_certificates(bundle);
+        assert_eq!(certificates.len(), 2);
+        assert_eq!(
+            certificates[1],
+            "-----BEGIN CERTIFICATE-----\nMIIC\n-----END CERTIFICATE-----"
+        );
+        assert!(pem_certificates("not a certificate").is_empty());
+    }
+
+    #[test]
+    fn test_build_client() {
+        let http_config = HttpConfig {
+            proxy: Some(String::from("socks5h://127.0.0.1:1080")),
+            ..HttpConfig::default()
+        };
+        assert!(build_client(&http_config).is_ok());
+        let http_config = HttpConfig {
+            ca_bundle: Some(String::from("/nonexistent/ca.pem")),
+            ..HttpConfig::default()
+        };
+        let err = build_client(&http_config).err().unwrap();
+        assert!(matches!(err, BotError::Io { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_get_retries_retryable_status() {
+        let url = serve(vec![
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 429 Too Many Requests\r\nContent-Length: 46\r\nConnection: close\r\n\r\n{\"ok\":false,\"parameters\":{\"retry_after\":0}}",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        ]);
+        let body = get(&TestRequest { url }).await.unwrap();
+        assert_eq!(body, "ok");
+    }
+
+    #[tokio::test]
+    async fn test_get_does_not_retry_other_status() {
+        let url = serve(vec![
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\nConnection: close\r\n\r\nnot found",
+        ]);
+        let err = get(&TestRequest { url }).await.err().unwrap();
+        assert_eq!(err.to_string(), "test returned HTTP 404: not found");
+        assert!(matches!(err, BotError::HttpStatus { status: 404, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_post_is_not_sent_again_after_lost_response() {
+        // the connection is closed without a response, the post may have been received
+        let url = serve(vec![
+            "",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        ]);
+        assert!(post_json(&TestRequest { url: url.clone() }).await.is_err());
+        let url = serve(vec![
+            "",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        ]);
+        assert_eq!(get(&TestRequest { url }).await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn test_post_is_not_sent_again_after_gateway_error() {
+        let url = serve(vec![
+            "HTTP/1.1 502 Bad Gateway\r\nContent-Length: 3\r\nConnection: close\r\n\r\nbad",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        ]);
+        let err = post_json(&TestRequest { url: url.clone() })
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, BotError::HttpStatus { status: 502, .. }));
+        // the second response is still waiting: the post was sent once
+        assert_eq!(get(&TestRequest { url }).await.unwrap(), "ok");
+        // a rate limit is retried, the post was not handled
+        let url = serve(vec![
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 4\r\nConnection: close\r\n\r\nslow",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        ]);
+        assert_eq!(post_json(&TestRequest { url }).await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn test_get_returns_last_retryable_status() {
+        let response = "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\nContent-Length: 4\r\nConnection: close\r\n\r\ndown";
+        let url = serve(vec![response; 3]);
+        let err = get(&TestRequest { url }).await.err().unwrap();
+        assert!(matches!(err, BotError::HttpStatus { status: 503, .. }));
+    }
+
+    #[test]
+    fn test_retry_after_header() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::RETRY_AFTER,
+            reqwest::header::HeaderValue::from_static("7"),
+        );
+        assert_eq!(retry_after_header(&headers), Some(Duration::from_secs(7)));
+        headers.insert(
+            reqwest::header::RETRY_AFTER,
+            reqwest::header::HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after_header(&headers), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_retry_delay_is_capped() {
+        let retry_config = RetryConfig::default();
+        let delay = retry_delay(&retry_config, 1, Some(Duration::from_secs(86400)));
+        assert_eq!(delay, Duration::from_secs(60));
+        let delay = retry_delay(&retry_config, 1, Some(Duration::from_secs(5)));
+        assert_eq!(delay, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_telegram_retry_after() {
+        let body = r#"{"ok":false,"error_code":429,"description":"Too Many Requests: retry after 5","parameters":{"retry_after":5}}"#;
+        assert_eq!(telegram_retry_after(body), Some(Duration::from_secs(5)));
+        assert_eq!(telegram_retry_after("{}"), None);
+    }
+
+    #[test]
+    fn test_record_file_name() {
+        let timestamp = chrono::Utc.with_ymd_and_hms(2023, 11, 2, 15, 0, 0).unwrap();
+        let file_name = record_file_name("https://api.tvmaze.com/schedule/web", timestamp, 1);
+        assert_eq!(file_name, "20231102T150000.000_0001_web.json");
+        let file_name = record_file_name(
+            "https://api.tvmaze.com/schedule/web?date=2023-11-01",
+            timestamp,
+            12,
+        );
+        assert_eq!(file_name, "20231102T150000.000_0012_web_2023-11-01.json");
+    }
+
+    #[test]
+    fn test_record_file_name_hides_token() {
+        let timestamp = chrono::Utc.with_ymd_and_hms(2023, 11, 2, 15, 0, 0).unwrap();
+        let url = "https://api.telegram.org/bot123:secret/sendPhoto";
+        let file_name = record_file_name(url, timestamp, 3);
+        assert_eq!(file_name, "20231102T150000.000_0003_sendPhoto.json");
+        assert_eq!(host_name(url), "api.telegram.org");
+    }
+}
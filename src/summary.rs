@@ -0,0 +1,175 @@
+use crate::error::BotError;
+use log::info;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+
+// Outcome of a run, for the exit code and the monitoring
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Success,
+    // some posts or images failed, others went out
+    PartialFailure,
+    // nothing could be published
+    TotalFailure,
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ChannelSummary {
+    pub posted: usize,
+    pub failed: usize,
+}
+
+// What a run (or a daemon wake-up) did, logged at the end of the run
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct RunSummary {
+    // new seasons returned by the sources
+    pub seasons_fetched: usize,
+    // schedule days that could not be fetched, their seasons are missing
+    pub days_failed: usize,
+    // schedule entries and seasons that were not published, by reason
+    pub skipped: BTreeMap<String, usize>,
+    pub queued: usize,
+    pub previewed: usize,
+    pub images_downloaded: usize,
+    pub images_failed: usize,
+    pub channels: BTreeMap<String, ChannelSummary>,
+    // the error that stopped the run, e.g. the schedule could not be fetched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RunSummary {
+    pub fn skip(&mut self, reason: &str, count: usize) {
+        if count > 0 {
+            *self.skipped.entry(reason.to_string()).or_default() += count;
+        }
+    }
+
+    pub fn posted(&mut self, channel: &str) {
+        self.channels.entry(channel.to_string()).or_default().posted += 1;
+    }
+
+    pub fn failed(&mut self, channel: &str) {
+        self.channels.entry(channel.to_string()).or_default().failed += 1;
+    }
+
+    pub fn status(&self) -> RunStatus {
+        let posted: usize = self.channels.values().map(|channel| channel.posted).sum();
+        let failed: usize = self.channels.values().map(|channel| channel.failed).sum();
+        if self.error.is_some() || (failed > 0 && posted == 0) {
+            RunStatus::TotalFailure
+        } else if failed > 0 || self.images_failed > 0 || self.days_failed > 0 {
+            RunStatus::PartialFailure
+        } else {
+            RunStatus::Success
+        }
+    }
+
+    pub fn log(&self) {
+        let skipped: Vec<String> = self
+            .skipped
+            .iter()
+            .map(|(reason, count)| format!("{} {}", count, reason))
+            .collect();
+        info!(
+            "Run summary: {} seasons fetched, {} days failed, {} queued, {} previewed, images: {} downloaded, {} failed; skipped: {}",
+            self.seasons_fetched,
+            self.days_failed,
+            self.queued,
+            self.previewed,
+            self.images_downloaded,
+            self.images_failed,
+            if skipped.is_empty() {
+                String::from("none")
+            } else {
+                skipped.join(", ")
+            }
+        );
+        for (channel, summary) in self.channels.iter() {
+            info!(
+                "Run summary: {} posted {}, failed {}",
+                channel, summary.posted, summary.failed
+            );
+        }
+    }
+
+    // The summary with the status and the time, for the monitoring
+    pub fn to_json(&self) -> Result<String, BotError> {
+        #[derive(Serialize)]
+        struct Report<'a> {
+            finished_at: String,
+            status: RunStatus,
+            #[serde(flatten)]
+            summary: &'a RunSummary,
+        }
+        let report = Report {
+            finished_at: chrono::Utc::now().to_rfc3339(),
+            status: self.status(),
+            summary: self,
+        };
+        serde_json::to_string_pretty(&report)
+            .map_err(|err| BotError::Parse(format!("run summary: {}", err)))
+    }
+
+    // Log the summary and overwrite the summary file, if set
+    pub fn report(&self, summary_file: Option<&str>) {
+        self.log();
+        let Some(summary_file) = summary_file else {
+            return;
+        };
+        let written = self.to_json().and_then(|json| {
+            fs::write(summary_file, json).map_err(|err| BotError::io(summary_file, err))
+        });
+        if let Err(err) = written {
+            log::error!("Cannot write run summary: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status() {
+        let mut summary = RunSummary::default();
+        assert_eq!(summary.status(), RunStatus::Success);
+        summary.posted("mastodon");
+        summary.images_failed = 1;
+        assert_eq!(summary.status(), RunStatus::PartialFailure);
+        summary.images_failed = 0;
+        summary.days_failed = 1;
+        assert_eq!(summary.status(), RunStatus::PartialFailure);
+        summary.days_failed = 0;
+        summary.failed("telegram");
+        assert_eq!(summary.status(), RunStatus::PartialFailure);
+        let mut summary = RunSummary::default();
+        summary.failed("mastodon");
+        summary.failed("telegram");
+        assert_eq!(summary.status(), RunStatus::TotalFailure);
+        let summary = RunSummary {
+            error: Some(String::from("tvmaze schedule")),
+            ..RunSummary::default()
+        };
+        assert_eq!(summary.status(), RunStatus::TotalFailure);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let mut summary = RunSummary {
+            seasons_fetched: 3,
+            ..RunSummary::default()
+        };
+        summary.skip("already published", 2);
+        summary.skip("duplicate", 0);
+        summary.posted("mastodon");
+        let json: serde_json::Value = serde_json::from_str(&summary.to_json().unwrap()).unwrap();
+        assert_eq!(json["status"], "success");
+        assert_eq!(json["seasons_fetched"], 3);
+        assert_eq!(json["skipped"]["already published"], 2);
+        assert!(json["skipped"].get("duplicate").is_none());
+        assert_eq!(json["channels"]["mastodon"]["posted"], 1);
+    }
+}
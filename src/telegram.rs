@@ -1,8 +1,13 @@
 use crate::apis;
 use crate::config::TelegramConfig;
-use crate::requests::{upload_file, FileUpload, RequestData};
-use crate::utils;
+use crate::error::BotError;
+use crate::preview::PostPreview;
+use crate::publisher::Publisher;
+use crate::requests::{file_part, post_json, post_multipart, RequestData};
+use crate::template::{Formatter, PostTemplate};
+use async_trait::async_trait;
 use reqwest::header::HeaderMap;
+use serde_derive::Deserialize;
 use std::collections::HashMap;
 
 const CAPTION_LENGTH: i32 = 1024;
@@ -23,6 +28,43 @@ impl PostMethod {
     }
 }
 
+// Error reported by the bot api, e.g. {"ok":false,"error_code":400,"description":"Bad Request: chat not found"}
+fn telegram_error(body: &str) -> BotError {
+    let (status, message) = match serde_json::from_str::<TelegramResponse>(body) {
+        Ok(response) => (
+            response.error_code.map(|error_code| error_code as u16),
+            response
+                .description
+                .unwrap_or_else(|| String::from("no description")),
+        ),
+        Err(_) => (
+            None,
+            format!(
+                "unexpected response: {}",
+                body.chars().take(200).collect::<String>()
+            ),
+        ),
+    };
+    BotError::Platform {
+        platform: String::from("telegram"),
+        status,
+        message,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TelegramResponse {
+    ok: bool,
+    error_code: Option<i64>,
+    description: Option<String>,
+    result: Option<TelegramMessage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TelegramMessage {
+    message_id: i64,
+}
+
 #[derive(Debug)]
 pub struct TelegramPost<'a> {
     pub post_text: String,
@@ -32,27 +74,8 @@ pub struct TelegramPost<'a> {
 }
 
 impl<'a> TelegramPost<'a> {
-    pub fn from_season_data(
-        data: &apis::SeasonData,
-        config: &'a TelegramConfig,
-        image_path: Option<String>,
-    ) -> Self {
-        let language = utils::hashtag_string_or_na(&data.language);
-        let genres = utils::get_genres(&data.genres);
-        let when = utils::get_when();
-        let description = utils::string_or_na(&data.description);
-        let host = utils::hashtag_string_or_na(&data.host);
-        let post = format!(
-            "{}\n\
-            {}\n\n\
-            Host: {}\n\
-            When: {}\n\
-            Season: {}\n\
-            Language: {}\n\
-            Genres: {}\n\n\
-            {}\n",
-            &data.title, &data.url, host, when, &data.season_number, language, genres, description,
-        );
+    // Post with the text from any formatter, trimmed to the length limit
+    pub fn new(post: String, config: &'a TelegramConfig, image_path: Option<String>) -> Self {
         let post_text = Self::trim_post(post, &image_path);
 
         let post_method = match image_path {
@@ -68,6 +91,39 @@ impl<'a> TelegramPost<'a> {
         }
     }
 
+    pub fn max_length(&self) -> i32 {
+        match self.post_method {
+            PostMethod::SendPhoto => CAPTION_LENGTH,
+            PostMethod::SendMessage => POST_LENGTH,
+        }
+    }
+
+    // Get the id of the sent message from the api response
+    pub fn post_id(response: &str) -> Result<String, BotError> {
+        match serde_json::from_str::<TelegramResponse>(response) {
+            Ok(TelegramResponse {
+                ok: true,
+                result: Some(message),
+                ..
+            }) => Ok(message.message_id.to_string()),
+            _ => Err(telegram_error(response)),
+        }
+    }
+
+    // Send the message or the photo and return the message id
+    pub async fn publish(&self) -> Result<String, BotError> {
+        let result = match self.post_method {
+            PostMethod::SendPhoto => post_multipart(self).await,
+            PostMethod::SendMessage => post_json(self).await,
+        };
+        // the bot api describes the error in the body of non-2xx responses
+        let response = result.map_err(|err| match err {
+            BotError::HttpStatus { body, .. } => telegram_error(&body),
+            err => err,
+        })?;
+        Self::post_id(&response)
+    }
+
     fn trim_post(post: String, image_path: &Option<String>) -> String {
         let post_body_length = post.chars().count() as i32;
         let mut is_post_bigger = false;
@@ -129,21 +185,136 @@ impl<'a> RequestData for TelegramPost<'a> {
         body
     }
 
-    fn json_multipart(&self) -> reqwest::blocking::multipart::Form {
-        let chat_id = reqwest::blocking::multipart::Part::text(self.config.chat_id.clone());
-        let caption = reqwest::blocking::multipart::Part::text(self.post_text.clone());
-        let image_path = match self.image_path.clone() {
-            Some(image_path) => image_path,
-            None => String::from(""),
-        };
-        let form = reqwest::blocking::multipart::Form::new()
+    fn json_multipart(&self) -> Result<reqwest::multipart::Form, BotError> {
+        let chat_id = reqwest::multipart::Part::text(self.config.chat_id.clone());
+        let caption = reqwest::multipart::Part::text(self.post_text.clone());
+        let image_path = self.image_path.clone().unwrap_or_default();
+        Ok(reqwest::multipart::Form::new()
             .part("caption", caption)
             .part("chat_id", chat_id)
-            .file("photo", image_path);
+            .part("photo", file_part(&image_path)?))
+    }
+}
 
-        match form {
-            Ok(form) => form,
-            Err(_) => panic!("Cant read image for posting"),
-        }
+// Publishes the posts to the telegram chat from the config
+pub struct TelegramPublisher<'a> {
+    config: &'a TelegramConfig,
+    formatter: Box<dyn Formatter + 'a>,
+}
+
+impl<'a> TelegramPublisher<'a> {
+    pub fn new(config: &'a TelegramConfig) -> Self {
+        let template = PostTemplate::or_default(
+            "telegram",
+            config.template.as_deref(),
+            &config.translations,
+            config.tz,
+        );
+        Self::with_formatter(config, Box::new(template))
+    }
+
+    pub fn with_formatter(config: &'a TelegramConfig, formatter: Box<dyn Formatter + 'a>) -> Self {
+        Self { config, formatter }
+    }
+
+    fn post(&self, data: &apis::SeasonData, image_path: Option<String>) -> TelegramPost<'a> {
+        let post = self.formatter.format(data, image_path.is_some());
+        TelegramPost::new(post, self.config, image_path)
+    }
+}
+
+#[async_trait]
+impl<'a> Publisher for TelegramPublisher<'a> {
+    fn name(&self) -> &str {
+        "telegram"
+    }
+
+    async fn publish(
+        &self,
+        data: &apis::SeasonData,
+        image_path: Option<String>,
+    ) -> Result<String, BotError> {
+        self.post(data, image_path).publish().await
+    }
+
+    fn preview(&self, data: &apis::SeasonData, image_path: Option<String>) -> PostPreview {
+        PostPreview::from_telegram_post(&self.post(data, image_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::locale::Locale;
+
+    #[test]
+    fn test_max_length() {
+        let test_season_data = apis::SeasonData {
+            show_id: 1,
+            title: String::from("title"),
+            url: String::from("url"),
+            language: None,
+            description: None,
+            genres: vec![],
+            image_url: None,
+            season_number: 1,
+            host: None,
+            airstamp: None,
+            airtime: None,
+        };
+        let config = TelegramConfig {
+            token: String::from("telegram token"),
+            chat_id: String::from("telegram chat id"),
+            template: None,
+            locale: String::from("en"),
+            translations: Locale::default(),
+            tz: chrono_tz::UTC,
+        };
+        let template = PostTemplate::default_template("telegram", &config.translations, config.tz);
+        let post = TelegramPost::new(template.format(&test_season_data, false), &config, None);
+        assert_eq!(post.max_length(), POST_LENGTH);
+        let image_path = Some(String::from("/path/to/image.jpg"));
+        let text = template.format(&test_season_data, true);
+        let post = TelegramPost::new(text, &config, image_path);
+        assert_eq!(post.max_length(), CAPTION_LENGTH);
+    }
+
+    #[test]
+    fn test_post_id() {
+        let response = r#"{"ok": true, "result": {"message_id": 42, "text": "title"}}"#;
+        assert_eq!(TelegramPost::post_id(response).unwrap(), String::from("42"));
+    }
+
+    #[test]
+    fn test_post_id_not_ok() {
+        let response =
+            r#"{"ok": false, "error_code": 400, "description": "Bad Request: chat not found"}"#;
+        let err = TelegramPost::post_id(response).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "telegram HTTP 400: Bad Request: chat not found"
+        );
+        let err = TelegramPost::post_id("<html>Bad Gateway</html>").unwrap_err();
+        assert!(matches!(err, BotError::Platform { status: None, .. }));
+    }
+
+    #[test]
+    fn test_missing_image() {
+        let config = TelegramConfig {
+            token: String::from("telegram token"),
+            chat_id: String::from("telegram chat id"),
+            template: None,
+            locale: String::from("en"),
+            translations: Locale::default(),
+            tz: chrono_tz::UTC,
+        };
+        let post = TelegramPost {
+            post_text: String::from("title"),
+            config: &config,
+            image_path: Some(String::from("/nonexistent/image.jpg")),
+            post_method: PostMethod::SendPhoto,
+        };
+        let err = post.json_multipart().err().unwrap();
+        assert!(matches!(err, BotError::Io { .. }));
     }
 }
@@ -0,0 +1,415 @@
+use crate::apis::SeasonData;
+use crate::locale::Locale;
+use crate::utils;
+use chrono::Utc;
+use chrono_tz::Tz;
+use minijinja::{context, Environment, UndefinedBehavior, Value};
+use std::error::Error;
+use std::fmt;
+
+// Layout of the posts when no template is set in the config
+pub const DEFAULT_TEMPLATE: &str = "{{ title }}
+{{ url }}
+
+{{ labels.host }}: {{ host | hashtag | na }}
+{{ labels.when }}: {{ date }}{% if airtime %}, {{ airtime }}{% endif %}
+{{ labels.season }}: {{ season }}
+{{ labels.language }}: {{ language | hashtag | na }}
+{{ labels.genres }}: {{ genres | hashtag | na }}
+
+{{ description | na }}
+";
+
+#[derive(Debug)]
+pub struct TemplateError {
+    pub channel: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl TemplateError {
+    fn new(channel: &str, err: minijinja::Error) -> Self {
+        let message = match err.detail() {
+            Some(detail) => format!("{}: {}", err.kind(), detail),
+            None => err.kind().to_string(),
+        };
+        Self {
+            channel: channel.to_string(),
+            line: err.line(),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(
+                f,
+                "[{}] template, line {}: {}",
+                self.channel, line, self.message
+            ),
+            None => write!(f, "[{}] template: {}", self.channel, self.message),
+        }
+    }
+}
+
+impl Error for TemplateError {}
+
+// Turns a season into the text of a post
+pub trait Formatter: Send + Sync {
+    fn format(&self, data: &SeasonData, has_image: bool) -> String;
+    // The start of the post before the description, None when it cannot be told
+    fn headline(&self, _data: &SeasonData, _has_image: bool) -> Option<String> {
+        None
+    }
+}
+
+// Rendered in place of the description to find where it starts, a private
+// use char that the filters leave as it is
+const DESCRIPTION_MARKER: &str = "\u{E000}";
+
+// Compiled post template of one channel
+pub struct PostTemplate {
+    env: Environment<'static>,
+    channel: String,
+    locale: Locale,
+    timezone: Tz,
+}
+
+impl PostTemplate {
+    // Compile the template and render it with sample data,
+    // so that unknown placeholders and bad filters are reported at startup
+    pub fn new(
+        channel: &str,
+        source: Option<&str>,
+        locale: &Locale,
+        timezone: Tz,
+    ) -> Result<Self, TemplateError> {
+        let template = Self::compile(channel, source, locale, timezone)?;
+        template.render(&sample_season(true), true)?;
+        template.render(&sample_season(false), false)?;
+        Ok(template)
+    }
+
+    fn compile(
+        channel: &str,
+        source: Option<&str>,
+        locale: &Locale,
+        timezone: Tz,
+    ) -> Result<Self, TemplateError> {
+        let mut env = Environment::new();
+        env.set_keep_trailing_newline(true);
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.add_filter("hashtag", hashtag);
+        let not_available = locale.not_available.clone();
+        env.add_filter("na", move |value: Value| na(value, &not_available));
+        env.add_filter("truncate", truncate);
+        let source = source.unwrap_or(DEFAULT_TEMPLATE).to_string();
+        env.add_template_owned(channel.to_string(), source)
+            .map_err(|err| TemplateError::new(channel, err))?;
+        Ok(Self {
+            env,
+            channel: channel.to_string(),
+            locale: locale.clone(),
+            timezone,
+        })
+    }
+
+    pub fn render(&self, data: &SeasonData, has_image: bool) -> Result<String, TemplateError> {
+        // premiere date and time in the configured timezone, today if tvmaze has no airstamp
+        let airstamp = data
+            .airstamp
+            .map(|airstamp| airstamp.with_timezone(&self.timezone));
+        let date = match airstamp {
+            Some(airstamp) => self.locale.format_date(&airstamp),
+            None => self
+                .locale
+                .format_date(&Utc::now().with_timezone(&self.timezone)),
+        };
+        let airtime = match (airstamp, &data.airtime) {
+            (Some(airstamp), Some(_)) => Some(airstamp.format("%H:%M").to_string()),
+            _ => None,
+        };
+        let template = self
+            .env
+            .get_template(&self.channel)
+            .map_err(|err| TemplateError::new(&self.channel, err))?;
+        template
+            .render(context! {
+                title => data.title,
+                url => data.url,
+                host => data.host,
+                season => data.season_number,
+                language => data.language,
+                genres => data.genres,
+                description => data.description,
+                date => date,
+                airtime => airtime,
+                has_image => has_image,
+                labels => self.locale.labels,
+            })
+            .map_err(|err| TemplateError::new(&self.channel, err))
+    }
+}
+
+impl Formatter for PostTemplate {
+    // Render the post, falling back to the default template
+    fn format(&self, data: &SeasonData, has_image: bool) -> String {
+        match self.render(data, has_image) {
+            Ok(post) => post,
+            Err(err) => {
+                log::error!("Cannot render post, using the default template: {}", err);
+                Self::default_template(&self.channel, &self.locale, self.timezone)
+                    .render(data, has_image)
+                    .unwrap_or_else(|err| {
+                        log::error!("Cannot render the default template: {}", err);
+                        plain_text(data)
+                    })
+            }
+        }
+    }
+
+    // The template rendered up to the description, whatever filters are applied to it
+    fn headline(&self, data: &SeasonData, has_image: bool) -> Option<String> {
+        let mut data = data.clone();
+        data.description = Some(String::from(DESCRIPTION_MARKER));
+        let post = self.render(&data, has_image).ok()?;
+        let start = post.find(DESCRIPTION_MARKER)?;
+        Some(post[..start].to_string())
+    }
+}
+
+impl PostTemplate {
+    // Not rendered with sample data: rendering can still fail, e.g. with a
+    // label missing from the locale, and falls back to plain_text
+    pub fn default_template(channel: &str, locale: &Locale, timezone: Tz) -> Self {
+        Self::compile(channel, None, locale, timezone).expect("default template compiles")
+    }
+
+    // The channel template, or the default one if it does not compile
+    pub fn or_default(channel: &str, source: Option<&str>, locale: &Locale, timezone: Tz) -> Self {
+        Self::new(channel, source, locale, timezone).unwrap_or_else(|err| {
+            log::error!("Cannot compile template, using the default one: {}", err);
+            Self::default_template(channel, locale, timezone)
+        })
+    }
+}
+
+// The title, the link and the description, when no template can be rendered
+fn plain_text(data: &SeasonData) -> String {
+    let mut text = format!("{}\n{}\n", data.title, data.url);
+    if let Some(description) = &data.description {
+        text = text + "\n" + description + "\n";
+    }
+    text
+}
+
+fn sample_season(all_fields: bool) -> SeasonData {
+    let optional = |value: &str| all_fields.then(|| value.to_string());
+    SeasonData {
+        show_id: 1,
+        title: String::from("Title"),
+        url: String::from("https://www.tvmaze.com/shows/1/title"),
+        language: optional("English"),
+        description: optional("Description."),
+        genres: match all_fields {
+            true => vec![String::from("Drama")],
+            false => vec![],
+        },
+        image_url: optional("https://static.tvmaze.com/1.jpg"),
+        season_number: 1,
+        host: optional("Netflix"),
+        airstamp: all_fields.then(|| "2023-11-02T19:00:00Z".parse().unwrap()),
+        airtime: optional("21:00"),
+    }
+}
+
+// "Science-Fiction" -> "#ScienceFiction", lists are joined with spaces
+fn hashtag(value: Value) -> Value {
+    if value.is_none() || value.is_undefined() {
+        return value;
+    }
+    if let Some(s) = value.as_str() {
+        return Value::from(utils::hashtag(s));
+    }
+    match value.try_iter() {
+        Ok(items) => {
+            let tags: Vec<String> = items
+                .filter_map(|item| item.as_str().map(utils::hashtag))
+                .collect();
+            if tags.is_empty() {
+                Value::from(())
+            } else {
+                Value::from(tags.join(" "))
+            }
+        }
+        Err(_) => Value::from(utils::hashtag(&value.to_string())),
+    }
+}
+
+// Missing or empty values are rendered as N/A of the locale
+fn na(value: Value, not_available: &str) -> Value {
+    let is_empty = value.is_none()
+        || value.is_undefined()
+        || value.as_str().is_some_and(|s| s.is_empty())
+        || value.len() == Some(0);
+    if is_empty {
+        Value::from(not_available)
+    } else {
+        value
+    }
+}
+
+// Cut the text at a word boundary so that it is at most `length` chars with the "..."
+fn truncate(value: String, length: usize) -> String {
+    if value.chars().count() <= length {
+        return value;
+    }
+    let cut: String = value.chars().take(length.saturating_sub(3)).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(idx) => cut[..idx].trim_end().to_string(),
+        None => cut,
+    };
+    cut + "..."
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_template() {
+        let template = PostTemplate::new("mastodon", None, &Locale::default(), Tz::UTC).unwrap();
+        let post = template.render(&sample_season(true), true).unwrap();
+        let expected = "Title\n\
+            https://www.tvmaze.com/shows/1/title\n\n\
+            Host: #Netflix\n\
+            When: 02 November 2023, 19:00\n\
+            Season: 1\n\
+            Language: #English\n\
+            Genres: #Drama\n\n\
+            Description.\n";
+        assert_eq!(post, expected);
+    }
+
+    #[test]
+    fn test_timezone() {
+        let timezone: Tz = "Australia/Sydney".parse().unwrap();
+        let template = PostTemplate::new(
+            "mastodon",
+            Some("{{ date }} {{ airtime }}"),
+            &Locale::default(),
+            timezone,
+        )
+        .unwrap();
+        let post = template.render(&sample_season(true), true).unwrap();
+        assert_eq!(post, "03 November 2023 06:00");
+    }
+
+    #[test]
+    fn test_localized_default_template() {
+        let locale = Locale::load("de", None).unwrap();
+        let template = PostTemplate::new("mastodon", None, &locale, Tz::UTC).unwrap();
+        let post = template.render(&sample_season(false), false).unwrap();
+        let expected = format!(
+            "Title\n\
+            https://www.tvmaze.com/shows/1/title\n\n\
+            Anbieter: k. A.\n\
+            Wann: {}\n\
+            Staffel: 1\n\
+            Sprache: k. A.\n\
+            Genres: k. A.\n\n\
+            k. A.\n",
+            locale.format_date(&Utc::now())
+        );
+        assert_eq!(post, expected);
+    }
+
+    #[test]
+    fn test_custom_template() {
+        let source = "{{ title | upper }} S{{ season }}\n\
+            {% if has_image %}with image{% else %}no image{% endif %}\n\
+            {{ genres | hashtag | na }} {{ description | na | truncate(10) }}";
+        let template =
+            PostTemplate::new("telegram", Some(source), &Locale::default(), Tz::UTC).unwrap();
+        let mut data = sample_season(true);
+        data.description = Some(String::from("A long story about space"));
+        data.genres = vec![String::from("Science-Fiction"), String::from("Drama")];
+        let post = template.render(&data, false).unwrap();
+        assert_eq!(post, "TITLE S1\nno image\n#ScienceFiction #Drama A long...");
+    }
+
+    #[test]
+    fn test_hashtag() {
+        let template = PostTemplate::or_default(
+            "mastodon",
+            Some("{{ host | hashtag | na }}"),
+            &Locale::default(),
+            Tz::UTC,
+        );
+        let mut data = sample_season(false);
+        assert_eq!(template.format(&data, false), "N/A");
+        for (host, expected) in [
+            ("disnay +", "#disnay"),
+            ("chanal 4", "#chanal4"),
+            ("Science-Fiction", "#ScienceFiction"),
+        ] {
+            data.host = Some(String::from(host));
+            assert_eq!(template.format(&data, false), expected);
+        }
+    }
+
+    #[test]
+    fn test_headline() {
+        let source = "{{ title }}\n{{ url }}\n\n{{ description | upper | truncate(20) }}\n";
+        let template =
+            PostTemplate::new("mastodon", Some(source), &Locale::default(), Tz::UTC).unwrap();
+        assert_eq!(
+            template.headline(&sample_season(true), false).as_deref(),
+            Some("Title\nhttps://www.tvmaze.com/shows/1/title\n\n")
+        );
+        let template =
+            PostTemplate::new("mastodon", Some("{{ title }}"), &Locale::default(), Tz::UTC)
+                .unwrap();
+        assert_eq!(template.headline(&sample_season(true), false), None);
+    }
+
+    #[test]
+    fn test_plain_text_fallback() {
+        let mut locale = Locale::default();
+        locale.labels.remove("host");
+        let template = PostTemplate::default_template("mastodon", &locale, Tz::UTC);
+        assert_eq!(
+            template.format(&sample_season(true), true),
+            "Title\nhttps://www.tvmaze.com/shows/1/title\n\nDescription.\n"
+        );
+    }
+
+    #[test]
+    fn test_syntax_error_line() {
+        let source = "{{ title }}\n{{ url }}\n{% if host %}{{ host }}\n";
+        let err = PostTemplate::new("mastodon", Some(source), &Locale::default(), Tz::UTC)
+            .err()
+            .unwrap();
+        assert_eq!(err.channel, "mastodon");
+        assert!(err.line.is_some());
+        assert!(err.to_string().starts_with("[mastodon] template, line"));
+    }
+
+    #[test]
+    fn test_unknown_placeholder() {
+        let source = "{{ title }}\n{{ titel }}\n";
+        let err = PostTemplate::new("mastodon", Some(source), &Locale::default(), Tz::UTC)
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(2));
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate(String::from("short"), 10), "short");
+        assert_eq!(truncate(String::from("one two three"), 10), "one...");
+        assert_eq!(truncate(String::from("onetwothree"), 10), "onetwot...");
+    }
+}
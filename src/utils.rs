@@ -1,40 +1,148 @@
-pub fn hashtag_string_or_na(s: &Option<String>) -> String {
-    let raw_string = match s {
-        Some(s) => s.clone(),
-        None => "N/A".to_string(),
-    };
-    if raw_string == "N/A" {
-        raw_string
-    } else {
-        let mut hash_tag = String::from("#");
-        for i in raw_string.chars() {
-            if i.is_alphabetic() | i.is_numeric() {
-                hash_tag.push(i)
-            }
+use chrono::{NaiveDate, Utc};
+use chrono_tz::Tz;
+
+pub fn hashtag(s: &str) -> String {
+    let mut hash_tag = String::from("#");
+    for i in s.chars() {
+        if i.is_alphabetic() | i.is_numeric() {
+            hash_tag.push(i)
         }
-        hash_tag
     }
+    hash_tag
 }
 
-pub fn string_or_na(s: &Option<String>) -> String {
-    match s {
-        Some(s) => s.clone(),
-        None => "N/A".to_string(),
-    }
+// Current date in the given timezone
+pub fn today(timezone: &Tz) -> NaiveDate {
+    Utc::now().with_timezone(timezone).date_naive()
 }
 
-pub fn get_genres(genres: &Vec<String>) -> String {
-    if genres.is_empty() {
-        return "N/A".to_string();
-    };
-    let mut genres_tags: Vec<String> = vec![];
-    for i in genres.iter() {
-        let new_tag = hashtag_string_or_na(&Some(i.clone()));
-        genres_tags.push(new_tag);
-    }
-    genres_tags.join(" ")
+// Dates from `lookback_days` ago up to `target_date`, oldest first
+pub fn lookback_dates(target_date: NaiveDate, lookback_days: u32) -> Vec<NaiveDate> {
+    (0..=lookback_days)
+        .rev()
+        .map(|days_ago| target_date - chrono::Duration::days(days_ago as i64))
+        .collect()
+}
+
+// Every date from `from` to `to` inclusive
+pub fn date_range(from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+    from.iter_days().take_while(|date| *date <= to).collect()
 }
 
-pub fn get_when() -> String {
-    chrono::Utc::now().format("%d %B %Y").to_string()
+// TVMaze language names and their ISO 639-1 codes
+const LANGUAGE_CODES: [(&str, &str); 48] = [
+    ("Afrikaans", "af"),
+    ("Albanian", "sq"),
+    ("Arabic", "ar"),
+    ("Bengali", "bn"),
+    ("Bulgarian", "bg"),
+    ("Catalan", "ca"),
+    ("Chinese", "zh"),
+    ("Croatian", "hr"),
+    ("Czech", "cs"),
+    ("Danish", "da"),
+    ("Dutch", "nl"),
+    ("English", "en"),
+    ("Estonian", "et"),
+    ("Finnish", "fi"),
+    ("French", "fr"),
+    ("Georgian", "ka"),
+    ("German", "de"),
+    ("Greek", "el"),
+    ("Hebrew", "he"),
+    ("Hindi", "hi"),
+    ("Hungarian", "hu"),
+    ("Icelandic", "is"),
+    ("Indonesian", "id"),
+    ("Irish", "ga"),
+    ("Italian", "it"),
+    ("Japanese", "ja"),
+    ("Kannada", "kn"),
+    ("Korean", "ko"),
+    ("Latvian", "lv"),
+    ("Lithuanian", "lt"),
+    ("Malay", "ms"),
+    ("Malayalam", "ml"),
+    ("Marathi", "mr"),
+    ("Norwegian", "no"),
+    ("Persian", "fa"),
+    ("Polish", "pl"),
+    ("Portuguese", "pt"),
+    ("Romanian", "ro"),
+    ("Russian", "ru"),
+    ("Serbian", "sr"),
+    ("Slovak", "sk"),
+    ("Spanish", "es"),
+    ("Swedish", "sv"),
+    ("Tamil", "ta"),
+    ("Telugu", "te"),
+    ("Thai", "th"),
+    ("Turkish", "tr"),
+    ("Ukrainian", "uk"),
+];
+
+// "English" -> "en", None for languages without a known code
+pub fn language_code(language: &str) -> Option<&'static str> {
+    LANGUAGE_CODES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(language.trim()))
+        .map(|(_, code)| *code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookback_dates_today_only() {
+        let today = NaiveDate::from_ymd_opt(2023, 11, 2).unwrap();
+        assert_eq!(lookback_dates(today, 0), vec![today]);
+    }
+
+    #[test]
+    fn test_lookback_dates() {
+        let today = NaiveDate::from_ymd_opt(2023, 11, 2).unwrap();
+        let dates: Vec<String> = lookback_dates(today, 3)
+            .iter()
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .collect();
+        assert_eq!(
+            dates,
+            vec!["2023-10-30", "2023-10-31", "2023-11-01", "2023-11-02"]
+        );
+    }
+
+    #[test]
+    fn test_today() {
+        let utc_today = Utc::now().date_naive();
+        let days = (today(&chrono_tz::Pacific::Kiritimati) - utc_today).num_days();
+        assert!(days == 0 || days == 1);
+        let days = (today(&chrono_tz::Pacific::Pago_Pago) - utc_today).num_days();
+        assert!(days == 0 || days == -1);
+    }
+
+    #[test]
+    fn test_date_range() {
+        let from = NaiveDate::from_ymd_opt(2023, 10, 30).unwrap();
+        let to = NaiveDate::from_ymd_opt(2023, 11, 1).unwrap();
+        let dates: Vec<String> = date_range(from, to)
+            .iter()
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .collect();
+        assert_eq!(dates, vec!["2023-10-30", "2023-10-31", "2023-11-01"]);
+    }
+
+    #[test]
+    fn test_date_range_empty() {
+        let from = NaiveDate::from_ymd_opt(2023, 11, 2).unwrap();
+        let to = NaiveDate::from_ymd_opt(2023, 11, 1).unwrap();
+        assert!(date_range(from, to).is_empty());
+    }
+
+    #[test]
+    fn test_language_code() {
+        assert_eq!(language_code("English"), Some("en"));
+        assert_eq!(language_code("japanese"), Some("ja"));
+        assert_eq!(language_code("Klingon"), None);
+    }
 }